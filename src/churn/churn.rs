@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Exp};
+use tokio::time::{Duration, Instant};
+
+use crate::config::{Availability, ChurnModel};
+
+enum Changes {
+    // Online and offline periods drawn around the given means
+    Random { mtbf: Duration, mttr: Duration },
+    // Every remaining time at which the node goes offline or comes
+    // back, alternately
+    Schedule(VecDeque<Instant>),
+}
+
+// Tracks whether a node is online, telling it when it next fails or
+// comes back; every node starts out online
+pub struct Churn {
+    online: bool,
+    deregister: bool,
+    next_change: Option<Instant>,
+    changes: Changes,
+    rng: StdRng,
+}
+
+impl Churn {
+    pub fn new(availability: &Availability, rng: StdRng) -> Self {
+        let now = Instant::now();
+        let changes = match &availability.model {
+            // A node that would fail and be repaired instantly never
+            // changes at all
+            ChurnModel::Random {
+                mtbf_millis: 0,
+                mttr_millis: 0,
+            } => Changes::Schedule(VecDeque::new()),
+            ChurnModel::Random {
+                mtbf_millis,
+                mttr_millis,
+            } => Changes::Random {
+                mtbf: Duration::from_millis(*mtbf_millis),
+                mttr: Duration::from_millis(*mttr_millis),
+            },
+            ChurnModel::Schedule(windows) => {
+                // Merge overlapping windows, so that the node's state
+                // alternates at every change
+                let mut windows = windows
+                    .iter()
+                    .filter(|window| window.end_millis > window.start_millis)
+                    .map(|window| (window.start_millis, window.end_millis))
+                    .collect::<Vec<(u64, u64)>>();
+                windows.sort_unstable();
+                let mut merged: Vec<(u64, u64)> = vec![];
+                for (start, end) in windows {
+                    match merged.last_mut() {
+                        Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                        _ => merged.push((start, end)),
+                    }
+                }
+                Changes::Schedule(
+                    merged
+                        .into_iter()
+                        .flat_map(|(start, end)| [start, end])
+                        .map(|millis| now + Duration::from_millis(millis))
+                        .collect(),
+                )
+            }
+        };
+        let mut churn = Self {
+            online: true,
+            deregister: availability.deregister.unwrap_or_default(),
+            next_change: None,
+            changes,
+            rng,
+        };
+        churn.next_change = churn.draw_next_change(now);
+        churn
+    }
+
+    pub fn online(&self) -> bool {
+        self.online
+    }
+
+    // Whether the node leaves the directory while offline
+    pub fn deregisters(&self) -> bool {
+        self.deregister
+    }
+
+    // When the node next goes offline or comes back, if ever
+    pub fn next_change(&self) -> Option<Instant> {
+        self.next_change
+    }
+
+    // Called once the time returned by `next_change` has come, taking
+    // the node offline or bringing it back; returns whether it is now
+    // online
+    pub fn change(&mut self, now: Instant) -> bool {
+        self.online = !self.online;
+        self.next_change = self.draw_next_change(now);
+        self.online
+    }
+
+    fn draw_next_change(&mut self, now: Instant) -> Option<Instant> {
+        match &mut self.changes {
+            Changes::Random { mtbf, mttr } => {
+                let mean = match self.online {
+                    true => *mtbf,
+                    false => *mttr,
+                };
+                let period = match Exp::new(1.0 / mean.as_secs_f64()) {
+                    Ok(exp) if !mean.is_zero() => {
+                        Duration::from_secs_f64(exp.sample(&mut self.rng))
+                    }
+                    _ => Duration::ZERO,
+                };
+                Some(now + period)
+            }
+            Changes::Schedule(changes) => changes.pop_front(),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+mod churn;
+
+pub use churn::Churn;
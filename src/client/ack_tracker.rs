@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use rand::{rngs::StdRng, Rng};
+use tokio::time::{Duration, Instant};
+
+use crate::{config::Acks, packet::Message};
+
+const DEFAULT_ACK_TIMEOUT_MILLIS: u64 = 20000;
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+// A sent message that has not been acknowledged yet
+pub struct PendingMessage {
+    // Id the client gave the message when the user sent it, as opposed
+    // to the id it is acknowledged under
+    pub message_id: u64,
+    pub to: String,
+    pub body: String,
+    pub path_length: Option<usize>,
+    pub sent_at_micros: u64,
+    // Retransmitted copies carry the same SURB, so that the recipient
+    // can reply whichever copy reaches it
+    pub surb: Option<String>,
+    pub attempts: u32,
+    deadline: Instant,
+}
+
+// Keeps the messages a client has sent until they are acknowledged,
+// telling the client when to retransmit them and when to give up
+pub struct AckTracker {
+    timeout: Duration,
+    max_attempts: u32,
+    pending: HashMap<[u8; 16], PendingMessage>,
+    rng: StdRng,
+}
+
+impl AckTracker {
+    pub fn new(acks: &Acks, rng: StdRng) -> Self {
+        Self {
+            timeout: Duration::from_millis(
+                acks.timeout_millis.unwrap_or(DEFAULT_ACK_TIMEOUT_MILLIS),
+            ),
+            max_attempts: acks.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            pending: HashMap::new(),
+            rng,
+        }
+    }
+
+    // Draws the id that the next message is sent and acknowledged under
+    pub fn new_id(&mut self) -> [u8; 16] {
+        self.rng.random()
+    }
+
+    // Starts waiting for the ACK of a message that has just been sent
+    // for the first time
+    pub fn track(
+        &mut self,
+        id: [u8; 16],
+        message_id: u64,
+        to: &str,
+        message: &Message,
+        path_length: Option<usize>,
+    ) {
+        self.retrack(
+            id,
+            PendingMessage {
+                message_id,
+                to: to.to_owned(),
+                body: message.body.clone(),
+                path_length,
+                sent_at_micros: message.sent_at_micros,
+                surb: message.surb.clone(),
+                attempts: 1,
+                deadline: Instant::now(),
+            },
+        );
+    }
+
+    // Waits for the ACK of a message again after sending it once more
+    pub fn retrack(&mut self, id: [u8; 16], mut message: PendingMessage) {
+        message.deadline = Instant::now() + self.timeout;
+        self.pending.insert(id, message);
+    }
+
+    // Stops waiting for an acknowledged message, returning it if it was
+    // still being waited for
+    pub fn acknowledge(&mut self, id: &[u8; 16]) -> Option<PendingMessage> {
+        self.pending.remove(id)
+    }
+
+    // When the ACK of some message is next overdue, if ever
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|message| message.deadline).min()
+    }
+
+    // Removes and returns every message whose ACK is overdue by `now`,
+    // in the order they were due
+    pub fn take_overdue(&mut self, now: Instant) -> Vec<([u8; 16], PendingMessage)> {
+        let mut overdue_ids = self
+            .pending
+            .iter()
+            .filter(|(_, message)| message.deadline <= now)
+            .map(|(id, message)| (message.deadline, *id))
+            .collect::<Vec<(Instant, [u8; 16])>>();
+        overdue_ids.sort_unstable();
+        overdue_ids
+            .into_iter()
+            .filter_map(|(_, id)| self.pending.remove(&id).map(|message| (id, message)))
+            .collect()
+    }
+
+    // Whether a message that has been sent `attempts` times may be sent
+    // again
+    pub fn may_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+}
@@ -1,47 +1,95 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{HashMap, HashSet},
     time::Duration,
 };
 
-use prometheus_client::metrics::{counter::Counter, family::Family};
-use rand::prelude::*;
-use sphinx_packet::{
-    header::delays,
-    route::{Destination, DestinationAddressBytes, Node, NodeAddressBytes},
-    ProcessedPacketData, SphinxPacket,
+use prometheus_client::metrics::{
+    counter::Counter, family::Family, gauge::Gauge, histogram::Histogram,
 };
+use rand::{prelude::*, rngs::StdRng};
+use sphinx_packet::{ProcessedPacketData, SURB};
 use tokio::{
-    sync::mpsc::{self, Receiver as MpscReceiver, Sender as MpscSender},
-    time::sleep,
+    sync::mpsc::{self, Sender as MpscSender},
+    time::{interval_at, sleep_until, Instant, MissedTickBehavior},
 };
-use x25519_dalek::{PublicKey, StaticSecret};
+use x25519_dalek::StaticSecret;
 
 use crate::{
-    bytes::{bytes_to_string_truncate_zeroes, str_to_byte_array_32},
-    client::{ClientCommand, ClientSendError},
+    bytes::bytes_to_string_truncate_zeroes,
+    churn::Churn,
+    client::{
+        AckTracker, ClientCommand, ClientSendError, CoverTraffic, DeliveryReport, DeliveryStatus,
+        Fragmenter, Mailbox, PendingMessage, QueuedMessage, ReceivedMessage, ReplyBlocks,
+        SendQueue,
+    },
+    config::{Network, Topology},
     directory::{
         DirectoryCommand, DirectoryRegistration, DirectoryRegistrationError,
-        GetDirectoryRegistrationError,
+        GetDirectoryRegistrationError, NodeRole,
+    },
+    mix::{Forwarder, NodeKeys, ProcessError},
+    packet::{CoverKind, Fragment, Message, Packet, PacketKind},
+    prometheus::{
+        CoverLabels, FragmentLabels, FragmentStatus, MessageLabels, MessageStatus, MetricFamilies,
+        NodeLabels, ReassemblyLabels, ReassemblyStatus, ReplayLabels, ReplayStatus, SlotKind,
+        SlotLabels, SurbLabels, SurbStatus,
     },
-    packet::{Message, Packet},
-    prometheus::{MessageLabels, MessageStatus, MetricFamilies},
-    server::{ServerCommand, ServerRegistration, ServerRegistrationError},
+    queue::{self, Overflow, QueueReceiver, QueueSender},
+    registration::{deregister_node, register_in_directory, rekey_node},
+    rng::{RngStream, Rngs},
+    routing::{
+        mix_layers, new_route_selector, RouteSelector, SphinxBuilder, DEFAULT_AVERAGE_DELAY_MILLIS,
+        DEFAULT_PATH_LENGTH,
+    },
+    server::{NodeTx, ServerCommand, ServerRegistration, ServerRegistrationError},
+    stats::Stats,
 };
 
 pub struct ClientMetrics {
     // messages_sent: Family<MessageLabels, Counter>,
     // messages_received: Family<MessageLabels, Counter>,
     messages: Family<MessageLabels, Counter>,
+    cover: Family<CoverLabels, Counter>,
+    send_slots: Family<SlotLabels, Counter>,
+    send_queue_length: Family<NodeLabels, Gauge>,
+    send_queue_delay: Family<NodeLabels, Histogram, fn() -> Histogram>,
+    surbs: Family<SurbLabels, Counter>,
+    online: Family<NodeLabels, Gauge>,
+    fragments: Family<FragmentLabels, Counter>,
+    reassemblies: Family<ReassemblyLabels, Counter>,
+    replays: Family<ReplayLabels, Counter>,
 }
 
 pub struct Client {
     id: String,
-    sk: StaticSecret,
+    keys: NodeKeys,
     address_book: HashMap<String, DirectoryRegistration>,
     directory_tx: MpscSender<DirectoryCommand>,
-    client_tx: MpscSender<ClientCommand>,
-    client_rx: MpscReceiver<ClientCommand>,
+    client_tx: QueueSender<ClientCommand>,
+    client_rx: QueueReceiver<ClientCommand>,
     metrics: Option<ClientMetrics>,
+    mailbox: Option<Mailbox>,
+    cover: Option<CoverTraffic>,
+    send_queue: Option<SendQueue>,
+    acks: Option<AckTracker>,
+    delivery_tx: Option<MpscSender<DeliveryReport>>,
+    reply_blocks: Option<ReplyBlocks>,
+    inbox_tx: Option<MpscSender<ReceivedMessage>>,
+    churn: Option<Churn>,
+    fragmenter: Option<Fragmenter>,
+    // Id given to the next message the user sends
+    next_message_id: u64,
+    forwarder: Forwarder,
+    stats: Stats,
+    topology: Topology,
+    // Number of mix node layers layered routes go through
+    layers: usize,
+    route_selector: Box<dyn RouteSelector>,
+    path_length: usize,
+    average_delay: Duration,
+    sphinx_builder: SphinxBuilder,
+    routing_rng: StdRng,
 }
 
 impl Client {
@@ -50,12 +98,20 @@ impl Client {
         directory_tx: MpscSender<DirectoryCommand>,
         buffer_size: usize,
         mf: &Option<MetricFamilies>,
+        rngs: &Rngs,
+        stats: Stats,
+        network: &Network,
     ) -> Self {
-        let (client_tx, client_rx) = mpsc::channel::<ClientCommand>(buffer_size);
-        let sk = StaticSecret::random();
+        let (client_tx, client_rx) = queue::channel::<ClientCommand>(buffer_size);
+        let mut key_rng = rngs.stream(RngStream::Keys, id);
+        let sk = StaticSecret::from(key_rng.random::<[u8; 32]>());
         Self {
             id: id.to_owned(),
-            sk,
+            keys: NodeKeys::new(
+                sk,
+                network.key_rotation_millis.map(Duration::from_millis),
+                rngs.stream(RngStream::KeyRotation, id),
+            ),
             address_book: HashMap::new(),
             directory_tx,
             client_tx,
@@ -64,18 +120,134 @@ impl Client {
                 // messages_sent: mf.messages_sent.clone(),
                 // messages_received: mf.messages_received.clone(),
                 messages: mf.messages.clone(),
+                cover: mf.cover.clone(),
+                send_slots: mf.send_slots.clone(),
+                send_queue_length: mf.send_queue_length.clone(),
+                send_queue_delay: mf.send_queue_delay.clone(),
+                surbs: mf.surbs.clone(),
+                online: mf.node_online.clone(),
+                fragments: mf.fragments.clone(),
+                reassemblies: mf.reassemblies.clone(),
+                replays: mf.replays.clone(),
             }),
+            mailbox: None,
+            cover: None,
+            send_queue: None,
+            acks: None,
+            delivery_tx: None,
+            reply_blocks: None,
+            inbox_tx: None,
+            churn: None,
+            fragmenter: None,
+            next_message_id: 0,
+            forwarder: Forwarder::new(
+                id,
+                "[CLIENT]",
+                Duration::ZERO,
+                &network.mixing.unwrap_or_default(),
+                network,
+                rngs,
+                stats.clone(),
+            ),
+            stats,
+            topology: network.topology.unwrap_or_default(),
+            layers: 0,
+            route_selector: new_route_selector(&network.route_selection.unwrap_or_default()),
+            path_length: network.path_length.unwrap_or(DEFAULT_PATH_LENGTH),
+            average_delay: Duration::from_millis(
+                network
+                    .average_delay_millis
+                    .unwrap_or(DEFAULT_AVERAGE_DELAY_MILLIS),
+            ),
+            sphinx_builder: SphinxBuilder::new(
+                id,
+                network.delay_distribution.unwrap_or_default(),
+                key_rng,
+                rngs.stream(RngStream::Delays, id),
+                rngs.stream(RngStream::PacketIds, id),
+            ),
+            routing_rng: rngs.stream(RngStream::Routing, id),
         }
     }
 
-    pub async fn listen(&mut self, server_tx: MpscSender<ServerCommand>) {
+    // Has the client drop packets when its queue is full, according to
+    // the overflow policy, rather than have the server drop whichever
+    // packet it cannot fit
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.client_rx.set_overflow(overflow);
+        self
+    }
+
+    // Tells the client how many layers of mix nodes there are, which
+    // layered routes go through one node of each
+    pub fn with_layers(mut self, layers: usize) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    // Has the client receive its packets through a mailbox at a provider
+    pub fn with_mailbox(mut self, mailbox: Mailbox) -> Self {
+        self.mailbox = Some(mailbox);
+        self
+    }
+
+    // Has the client send cover traffic alongside its user's messages
+    pub fn with_cover(mut self, cover: CoverTraffic) -> Self {
+        self.cover = Some(cover);
+        self
+    }
+
+    // Puts the client in constant-rate mode
+    pub fn with_send_queue(mut self, send_queue: SendQueue) -> Self {
+        self.send_queue = Some(send_queue);
+        self
+    }
+
+    // Has the client acknowledge messages it receives and retransmit
+    // its own until acknowledged, reporting what came of each of them
+    // to its user
+    pub fn with_acks(mut self, acks: AckTracker, delivery_tx: MpscSender<DeliveryReport>) -> Self {
+        self.acks = Some(acks);
+        self.delivery_tx = Some(delivery_tx);
+        self
+    }
+
+    // Has the client attach a SURB to every message it sends, and hand
+    // the messages it receives to its user, who can reply through
+    // their SURBs
+    pub fn with_surbs(
+        mut self,
+        reply_blocks: ReplyBlocks,
+        inbox_tx: MpscSender<ReceivedMessage>,
+    ) -> Self {
+        self.reply_blocks = Some(reply_blocks);
+        self.inbox_tx = Some(inbox_tx);
+        self
+    }
+
+    // Has the client go offline and come back according to the given
+    // availability model, rather than drop packets by chance
+    pub fn with_churn(mut self, churn: Churn) -> Self {
+        self.churn = Some(churn);
+        self.forwarder.disable_random_drops();
+        self
+    }
+
+    // Has the client split messages too large for a single packet into
+    // fragments, and put together the fragmented messages it receives
+    pub fn with_fragmentation(mut self, fragmenter: Fragmenter) -> Self {
+        self.fragmenter = Some(fragmenter);
+        self
+    }
+
+    pub async fn listen(&mut self, server_tx: QueueSender<ServerCommand>) {
         // Register client at server
         let (response_tx, mut response_rx) =
             mpsc::channel::<Result<(), ServerRegistrationError>>(1);
         let cmd = ServerCommand::Register(
             ServerRegistration {
                 id: self.id.clone(),
-                tx: Some(self.client_tx.clone()),
+                tx: Some(NodeTx::Client(self.client_tx.clone())),
             },
             response_tx,
         );
@@ -103,9 +275,121 @@ impl Client {
             }
         };
 
+        self.record_online();
+
         // Loop listening to incoming commands
         println!("[CLIENT][{}] Starting listening", &self.id);
-        while let Some(cmd) = self.client_rx.recv().await {
+        let mut fetch_timer = self.mailbox.as_ref().map(|mailbox| {
+            let mut timer = interval_at(
+                Instant::now() + mailbox.fetch_interval,
+                mailbox.fetch_interval,
+            );
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            timer
+        });
+        // Routes are built from the address book, so it is looked up
+        // again as the keys in it rotate
+        let mut lookup_timer = self.keys.lookup_interval();
+        loop {
+            let next_wakeup = self.forwarder.next_wakeup();
+            let next_cover = self.cover.as_ref().and_then(CoverTraffic::next_due);
+            let next_slot = self.send_queue.as_ref().map(SendQueue::next_slot);
+            let next_ack_deadline = self.acks.as_ref().and_then(AckTracker::next_deadline);
+            let next_churn = self.churn.as_ref().and_then(Churn::next_change);
+            let next_reassembly_deadline =
+                self.fragmenter.as_ref().and_then(Fragmenter::next_deadline);
+            let next_rotation = self.keys.next_rotation();
+            let cmd = tokio::select! {
+                cmd = self.client_rx.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => return,
+                },
+                _ = async {
+                    match &mut fetch_timer {
+                        Some(timer) => timer.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => ClientCommand::FetchMailbox,
+                _ = async {
+                    match next_wakeup {
+                        Some(next_wakeup) => sleep_until(next_wakeup).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.forwarder.wake_up(&server_tx).await;
+                    continue;
+                }
+                _ = async {
+                    match next_cover {
+                        Some(next_cover) => sleep_until(next_cover).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let due = self
+                        .cover
+                        .as_mut()
+                        .map(|cover| cover.take_due(Instant::now()))
+                        .unwrap_or_default();
+                    for kind in due {
+                        self.send_cover(kind, &server_tx).await;
+                    }
+                    continue;
+                }
+                _ = async {
+                    match next_slot {
+                        Some(next_slot) => sleep_until(next_slot).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.use_send_slot(&server_tx).await;
+                    continue;
+                }
+                _ = async {
+                    match next_ack_deadline {
+                        Some(next_ack_deadline) => sleep_until(next_ack_deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.check_acks(&server_tx).await;
+                    continue;
+                }
+                _ = async {
+                    match next_churn {
+                        Some(next_churn) => sleep_until(next_churn).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.change_availability().await;
+                    continue;
+                }
+                _ = async {
+                    match next_reassembly_deadline {
+                        Some(next_reassembly_deadline) => sleep_until(next_reassembly_deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.check_reassembly();
+                    continue;
+                }
+                _ = async {
+                    match next_rotation {
+                        Some(next_rotation) => sleep_until(next_rotation).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.rotate_keys().await;
+                    continue;
+                }
+                _ = async {
+                    match &mut lookup_timer {
+                        Some(timer) => timer.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.refresh_address_book().await;
+                    continue;
+                }
+            };
             match cmd {
                 // Shutdown the client
                 ClientCommand::Shutdown => {
@@ -115,13 +399,7 @@ impl Client {
                 ClientCommand::Register => {
                     let (response_tx, mut response_rx) =
                         mpsc::channel::<Result<(), DirectoryRegistrationError>>(1);
-                    let cmd = DirectoryCommand::Register(
-                        DirectoryRegistration {
-                            id: self.id.clone(),
-                            pk: PublicKey::from(&self.sk),
-                        },
-                        response_tx,
-                    );
+                    let cmd = DirectoryCommand::Register(self.registration(), response_tx);
                     if let Err(e) = self.directory_tx.send(cmd).await {
                         eprintln!(
                             "[CLIENT][{}] Failed to send registration request: {e}",
@@ -152,55 +430,125 @@ impl Client {
                         }
                     };
                 }
+                // Come online and collect the packets waiting at the
+                // client's provider
+                ClientCommand::FetchMailbox => {
+                    if !self.online() {
+                        continue;
+                    }
+                    if let Some(mailbox) = &self.mailbox {
+                        let cmd =
+                            ServerCommand::FetchMailbox(mailbox.provider.clone(), self.id.clone());
+                        if let Err(e) = server_tx.send(cmd).await {
+                            eprintln!(
+                                "[CLIENT][{}] Failed to fetch mailbox from \"{}\": {e}",
+                                &self.id, &mailbox.provider
+                            );
+                        }
+                    }
+                }
                 // Receive a packet from another user
                 ClientCommand::ReceivePacket(packet) => {
+                    let kind = packet.kind();
+                    if !self.online() {
+                        eprintln!(
+                            "[CLIENT][{}] Node is offline, dropping packet from \"{}\"",
+                            &self.id,
+                            packet.from()
+                        );
+                        self.stats.record_dropped(kind);
+                        continue;
+                    }
                     let (packet_id, _, from, sphinx_packet) = packet.take();
-                    match sphinx_packet.process(&self.sk) {
+                    match self.keys.process(sphinx_packet) {
                         Ok(packet) => match packet.data {
                             ProcessedPacketData::ForwardHop {
                                 next_hop_packet,
                                 next_hop_address,
                                 delay,
                             } => {
-                                let rand_val = rand::random_range(0..100);
-                                if rand_val < 70 {
-                                    let to = bytes_to_string_truncate_zeroes(
-                                        next_hop_address.as_bytes(),
-                                    );
-                                    println!(
-                                        "[CLIENT][{}] Forwarding packet from \"{}\" to \"{}\"",
-                                        &self.id, &from, &to
-                                    );
-                                    let packet = Packet::new_with_id(
-                                        &packet_id,
-                                        &to,
-                                        &self.id,
-                                        next_hop_packet,
-                                    );
-                                    sleep(delay.to_duration()).await;
-                                    if let Err(e) =
-                                        server_tx.send(ServerCommand::Send(packet)).await
-                                    {
-                                        eprintln!("[CLIENT][{}] Unable to forward packet received from \"{}\" to \"{}\": {e}", &self.id, &from, &to);
-                                    }
-                                } else {
-                                    eprintln!(
-                                        "[CLIENT][{}] Client is unavailable at this time",
-                                        &self.id
-                                    );
-                                }
+                                self.forwarder.forward(
+                                    &packet_id,
+                                    kind,
+                                    &from,
+                                    next_hop_packet,
+                                    next_hop_address,
+                                    delay,
+                                );
                             }
                             ProcessedPacketData::FinalHop {
                                 destination,
-                                identifier: _,
+                                identifier,
                                 payload,
                             } => {
                                 let to_addr =
                                     bytes_to_string_truncate_zeroes(destination.as_bytes_ref());
                                 let payload_bytes = payload.recover_plaintext().unwrap();
                                 if to_addr == self.id {
-                                    let message: Message =
+                                    let mut message: Message =
                                         serde_yaml::from_slice(&payload_bytes).unwrap();
+                                    // A fragment is held until the rest of
+                                    // its message arrives
+                                    if let PacketKind::Fragment(id) = message.kind {
+                                        let Some(whole) =
+                                            self.receive_fragment(id, message.fragment.take())
+                                        else {
+                                            continue;
+                                        };
+                                        message = whole;
+                                    }
+                                    let sent_at = Duration::from_micros(message.sent_at_micros);
+                                    // A replay of a packet whose original
+                                    // never arrived is still handed over,
+                                    // but not counted as a delivery, since
+                                    // the original was counted as dropped
+                                    let replayed = kind == PacketKind::Replay;
+                                    if replayed {
+                                        println!(
+                                            "[CLIENT][{}] Accepted replayed packet from \"{from}\"",
+                                            &self.id
+                                        );
+                                        self.record_replay(ReplayStatus::Accepted);
+                                    }
+                                    match message.kind {
+                                        PacketKind::Cover(cover) => {
+                                            self.receive_cover(cover);
+                                            continue;
+                                        }
+                                        PacketKind::Ack => {
+                                            self.receive_ack(identifier).await;
+                                            continue;
+                                        }
+                                        PacketKind::Reply => {
+                                            self.receive_reply(identifier, message.body).await;
+                                            continue;
+                                        }
+                                        // Fragments only ever carry whole messages, and
+                                        // replays are only ever marked on the packet
+                                        PacketKind::Fragment(_) | PacketKind::Replay => continue,
+                                        // Every copy is acknowledged, since the ACK
+                                        // of an earlier one may have been lost
+                                        PacketKind::ReliableMessage => {
+                                            if let Some(from) = &message.from {
+                                                self.send_ack(from, identifier, &server_tx).await;
+                                            }
+                                            if !self
+                                                .stats
+                                                .record_reliable_delivered(identifier, sent_at)
+                                            {
+                                                println!(
+                                                    "[CLIENT][{}] Received another copy of message: {}",
+                                                    &self.id, message.body,
+                                                );
+                                                continue;
+                                            }
+                                        }
+                                        PacketKind::Message => {
+                                            if !replayed {
+                                                self.stats.record_delivered(sent_at);
+                                            }
+                                        }
+                                    }
                                     println!(
                                         "[CLIENT][{}] Received message: {}",
                                         &self.id, message.body,
@@ -209,187 +557,1293 @@ impl Client {
                                         metrics
                                             .messages
                                             .get_or_create(&MessageLabels {
-                                                from: message.from.unwrap_or("None".to_owned()),
+                                                from: message
+                                                    .from
+                                                    .clone()
+                                                    .unwrap_or("None".to_owned()),
                                                 to: self.id.clone(),
                                                 status: MessageStatus::Received,
                                             })
                                             .inc();
                                     }
+                                    let surb = message
+                                        .surb
+                                        .as_deref()
+                                        .and_then(|surb| self.receive_surb(surb));
+                                    self.hand_to_user(ReceivedMessage {
+                                        from: message.from,
+                                        body: message.body,
+                                        surb,
+                                    })
+                                    .await;
                                 } else {
                                     eprintln!("[CLIENT][{}] Do not support forwarding plaintexts at this time", &self.id);
+                                    self.stats.record_dropped(kind);
                                 }
                             }
                         },
+                        Err(ProcessError::Replayed) => {
+                            eprintln!(
+                                "[CLIENT][{}] Dropping replayed packet from \"{from}\"",
+                                &self.id
+                            );
+                            self.record_replay(ReplayStatus::Dropped);
+                        }
                         Err(e) => {
                             eprintln!(
                                 "[CLIENT][{}] Failed to process Sphinx packet from \"{}\": {e}",
                                 &self.id, from
                             );
+                            self.stats.record_dropped(kind);
                         }
                     }
                 }
+                // Reply to a received message through its SURB
+                ClientCommand::Reply(surb, body, response_tx) => {
+                    let reply_response = match self.online() {
+                        true => self.reply(surb, body, &server_tx).await,
+                        false => Err(ClientSendError::Offline),
+                    };
+                    if let Err(e) = response_tx.send(reply_response).await {
+                        eprintln!(
+                            "[CLIENT][{}] Failed to respond to request to reply through SURB: {e}",
+                            &self.id,
+                        );
+                    }
+                }
                 // Send a message to another user
-                ClientCommand::Send(to, body, response_tx) => {
-                    while self.address_book.len() < 3 {
-                        // Fetch all users from directory
-                        let (response_tx, mut response_rx) =
-                            mpsc::channel::<HashMap<String, DirectoryRegistration>>(1);
-                        let cmd = DirectoryCommand::GetAllRegistrations(response_tx);
-                        if let Err(e) = self.directory_tx.send(cmd).await {
+                ClientCommand::Send(to, body, path_length, response_tx) => {
+                    if self.stats.at_message_limit() {
+                        if let Err(e) = response_tx
+                            .send(Err(ClientSendError::MessageLimitReached))
+                            .await
+                        {
                             eprintln!(
-                                "[CLIENT][{}] Failed to fetch all users from directory: {e}",
-                                &self.id
+                                "[CLIENT][{}] Failed to respond to request to send message to \"{to}\": {e}",
+                                &self.id,
+                            );
+                        }
+                        continue;
+                    }
+                    if !self.online() {
+                        if let Err(e) = response_tx.send(Err(ClientSendError::Offline)).await {
+                            eprintln!(
+                                "[CLIENT][{}] Failed to respond to request to send message to \"{to}\": {e}",
+                                &self.id,
+                            );
+                        }
+                        continue;
+                    }
+                    let message_id = self.next_message_id;
+                    self.next_message_id += 1;
+                    // In constant-rate mode the message waits for the next
+                    // sending slot instead
+                    if let Some(send_queue) = self.send_queue.as_mut() {
+                        send_queue.push(QueuedMessage {
+                            id: message_id,
+                            to: to.clone(),
+                            body,
+                            path_length,
+                            sent_at_micros: self.stats.elapsed().as_micros() as u64,
+                            queued_at: Instant::now(),
+                        });
+                        let queue_length = send_queue.len();
+                        self.record_sent(&to);
+                        self.record_queue_length(queue_length);
+                        if let Err(e) = response_tx.send(Ok(message_id)).await {
+                            eprintln!(
+                                "[CLIENT][{}] Failed to respond to request to send message to \"{to}\": {e}",
+                                &self.id,
                             );
+                        }
+                        continue;
+                    }
+                    let path_length = path_length.unwrap_or(self.path_length);
+                    // Nodes may have registered since the address book
+                    // was last fetched; if there are still too few, the
+                    // send fails rather than hold up the client until
+                    // more show up
+                    if !self.knows_enough_nodes(&to, path_length) {
+                        if !self.refresh_address_book().await {
                             return;
                         }
-                        match response_rx.recv().await {
-                            Some(mut address_book) => {
-                                address_book.remove(&self.id);
-                                self.address_book = address_book;
+                        if !self.knows_enough_nodes(&to, path_length) {
+                            if let Err(e) =
+                                response_tx.send(Err(ClientSendError::NotEnoughNodes)).await
+                            {
+                                eprintln!(
+                                    "[CLIENT][{}] Failed to respond to request to send message to \"{to}\": {e}",
+                                    &self.id,
+                                );
                             }
-                            None => {
-                                eprintln!("[CLIENT][{}] Get all registrations response channel closed before receiving anything", &self.id);
-                                return;
+                            continue;
+                        }
+                    }
+                    if !self.address_book.contains_key(&to) {
+                        println!(
+                            "[CLIENT][{}] User \"{to}\" was not in address book, attempting to fetch from directory",
+                            &self.id,
+                        );
+                        if !self.fetch_registration(&to).await {
+                            if let Err(e) = response_tx
+                                .send(Err(ClientSendError::UnknownRecipient))
+                                .await
+                            {
+                                eprintln!(
+                                    "[CLIENT][{}] Failed to respond to request to send message to \"{to}\": {e}",
+                                    &self.id,
+                                );
                             }
+                            continue;
                         }
-                        sleep(Duration::from_millis(2000)).await;
                     }
-
-                    let mut forward_route_entries = self
-                        .address_book
-                        .values()
-                        .filter(|&entry| entry.id != to && entry.id != self.id)
-                        .choose_multiple(&mut rand::rng(), 3);
-                    forward_route_entries.shuffle(&mut rand::rng());
-                    let first_hop_id = match forward_route_entries.first() {
-                        Some(entry) => entry.id.clone(),
-                        None => to.clone(),
+                    let Some(recipient) = self.address_book.get(&to).cloned() else {
+                        continue;
                     };
-                    let mut route_string = forward_route_entries
-                        .iter()
-                        .fold(String::new(), |acc, entry| {
-                            acc + &format!("{} -> ", &entry.id)
-                        });
-                    route_string.push_str(&to);
+
+                    let forward_route_entries = self.select_route(&to, path_length);
                     println!(
-                        "[CLIENT][{}] Sending message through: {route_string}",
-                        &self.id
+                        "[CLIENT][{}] Sending message through: {}",
+                        &self.id,
+                        route_string(&forward_route_entries, &to)
                     );
-                    let mut forward_route = forward_route_entries
-                        .iter()
-                        .map(|entry| {
-                            Node::new(
-                                NodeAddressBytes::from_bytes(str_to_byte_array_32(&entry.id)),
-                                entry.pk,
-                            )
-                        })
-                        .collect::<Vec<Node>>();
-                    match self.address_book.entry(to) {
-                        Entry::Occupied(oe) => {
-                            let to = oe.key();
-                            let destination = Destination::new(
-                                DestinationAddressBytes::from_bytes(str_to_byte_array_32(to)),
-                                [0u8; 16],
-                            );
-                            // let sender = Destination::new(
-                            //     DestinationAddressBytes::from_bytes(str_to_byte_array_32(&self.id)),
-                            //     [0u8; 16],
-                            // );
-                            forward_route.push(Node::new(
-                                NodeAddressBytes::from_bytes(str_to_byte_array_32(to)),
-                                oe.get().pk,
-                            ));
-                            let message = Message {
-                                from: Some(self.id.clone()),
-                                body,
-                            };
-                            let message_yaml = serde_yaml::to_string(&message).unwrap();
-                            let body_bytes = message_yaml.as_bytes();
-                            let average_delay = Duration::from_secs(1);
-                            let delays = delays::generate_from_average_duration(
-                                forward_route.len(),
-                                average_delay,
-                            );
-                            match SphinxPacket::new(
-                                body_bytes.to_vec(),
-                                &forward_route,
-                                &destination,
-                                &delays,
-                            ) {
-                                Ok(sphinx_packet) => {
-                                    let packet =
-                                        Packet::new(&first_hop_id, &self.id, sphinx_packet);
-                                    // let packet_id = packet.id().to_owned();
-                                    let cmd = ServerCommand::Send(packet);
-                                    let send_response =
-                                        server_tx.send(cmd).await.map_err(ClientSendError::from);
-                                    if let Err(e) = &send_response {
-                                        eprintln!(
-                                            "[CLIENT][{}] Failed sending message to \"{to}\": {e}",
-                                            &self.id
-                                        );
-                                    } else if let Some(metrics) = &self.metrics {
-                                        metrics
-                                            .messages
-                                            .get_or_create(&MessageLabels {
-                                                from: self.id.clone(),
-                                                to: to.to_owned(),
-                                                status: MessageStatus::Sent,
-                                            })
-                                            .inc();
-                                    }
-                                    if let Err(e) = response_tx.send(send_response).await {
-                                        eprintln!(
-                                        "[CLIENT][{}] Failed to respond to request to send message to \"{}\": {e}",
-                                        &self.id, &to,
-                                    );
-                                    }
+                    let sent_at_micros = self.stats.elapsed().as_micros() as u64;
+                    let send_response = match self.message_packets(
+                        &forward_route_entries,
+                        &recipient,
+                        body,
+                        Some(path_length),
+                        sent_at_micros,
+                        message_id,
+                    ) {
+                        Ok(packets) => {
+                            let mut send_response = Ok(());
+                            for packet in packets {
+                                let cmd = ServerCommand::Send(packet);
+                                if let Err(e) = server_tx.send(cmd).await {
+                                    send_response = Err(ClientSendError::from(e));
+                                    break;
                                 }
-                                Err(e) => {
-                                    eprintln!(
-                                    "[CLIENT][{}] Failed to construct Sphinx packet to \"{to}\": {e}",
+                            }
+                            if let Err(e) = &send_response {
+                                eprintln!(
+                                    "[CLIENT][{}] Failed sending message to \"{to}\": {e}",
                                     &self.id
                                 );
-                                }
+                            } else {
+                                self.record_sent(&to);
                             }
+                            send_response.map(|_| message_id)
                         }
-                        Entry::Vacant(ve) => {
-                            println!(
-                            "[CLIENT][{}] User \"{}\" was not in address book, attempting to fetch from directory",
+                        Err(e) => {
+                            eprintln!(
+                                "[CLIENT][{}] Failed to wrap message to \"{to}\" in packets: {e}",
+                                &self.id
+                            );
+                            Err(e)
+                        }
+                    };
+                    if let Err(e) = response_tx.send(send_response).await {
+                        eprintln!(
+                            "[CLIENT][{}] Failed to respond to request to send message to \"{to}\": {e}",
                             &self.id,
-                            ve.key(),
                         );
+                    }
+                }
+            }
+        }
+    }
 
-                            let to = ve.key().to_owned();
-                            let (dir_response_tx, mut dir_response_rx) =
-                                mpsc::channel::<
-                                    Result<DirectoryRegistration, GetDirectoryRegistrationError>,
-                                >(1);
-                            let cmd =
-                                DirectoryCommand::GetRegistration(to.to_owned(), dir_response_tx);
-                            if let Err(e) = self.directory_tx.send(cmd).await {
-                                eprintln!("[CLIENT][{}] Failed sending get directory registration request for id \"{to}\": {e}", &self.id)
-                            }
-                            match dir_response_rx.recv().await {
-                                Some(Ok(registration)) => {
-                                    ve.insert(registration);
-                                    if let Err(e) = self.client_tx.send(ClientCommand::Send(to.to_owned(), body, response_tx)).await {
-                                        eprintln!("[CLIENT][{}] Failed forwarding send request after fetching missing user from directory: {e}", &self.id);
-                                    }
-                                }
-                                Some(Err(e)) => {
-                                    eprintln!("[CLIENT][{}] Failed fetching directory entry for user with id \"{to}\": {e}", &self.id);
-                                }
-                                None => eprintln!("[CLIENT][{}] Response channel closed before receiving directory entry for user with id \"{to}\"", &self.id),
-                            }
+    pub fn get_tx(&self) -> QueueSender<ClientCommand> {
+        self.client_tx.clone()
+    }
+
+    // The client's entry in the directory
+    fn registration(&self) -> DirectoryRegistration {
+        DirectoryRegistration {
+            id: self.id.clone(),
+            pk: self.keys.public_key(),
+            role: NodeRole::Client,
+            capacity: 1.0,
+            latency: Duration::ZERO,
+            average_delay: self.average_delay,
+            provider: self
+                .mailbox
+                .as_ref()
+                .map(|mailbox| mailbox.provider.clone()),
+        }
+    }
+
+    // Replaces the address book with every other node in the
+    // directory, returning whether the directory could be reached
+    async fn refresh_address_book(&mut self) -> bool {
+        let (response_tx, mut response_rx) =
+            mpsc::channel::<HashMap<String, DirectoryRegistration>>(1);
+        let cmd = DirectoryCommand::GetAllRegistrations(response_tx);
+        if let Err(e) = self.directory_tx.send(cmd).await {
+            eprintln!(
+                "[CLIENT][{}] Failed to fetch all users from directory: {e}",
+                &self.id
+            );
+            return false;
+        }
+        match response_rx.recv().await {
+            Some(mut address_book) => {
+                address_book.remove(&self.id);
+                self.address_book = address_book;
+                true
+            }
+            None => {
+                eprintln!("[CLIENT][{}] Get all registrations response channel closed before receiving anything", &self.id);
+                false
+            }
+        }
+    }
+
+    // Adds the registration of `to` from the directory to the address
+    // book, returning whether it was found
+    async fn fetch_registration(&mut self, to: &str) -> bool {
+        let (response_tx, mut response_rx) =
+            mpsc::channel::<Result<DirectoryRegistration, GetDirectoryRegistrationError>>(1);
+        let cmd = DirectoryCommand::GetRegistration(to.to_owned(), response_tx);
+        if let Err(e) = self.directory_tx.send(cmd).await {
+            eprintln!("[CLIENT][{}] Failed sending get directory registration request for id \"{to}\": {e}", &self.id)
+        }
+        match response_rx.recv().await {
+            Some(Ok(registration)) => {
+                self.address_book.insert(to.to_owned(), registration);
+                true
+            }
+            Some(Err(e)) => {
+                eprintln!(
+                    "[CLIENT][{}] Failed fetching directory entry for user with id \"{to}\": {e}",
+                    &self.id
+                );
+                false
+            }
+            None => {
+                eprintln!("[CLIENT][{}] Response channel closed before receiving directory entry for user with id \"{to}\"", &self.id);
+                false
+            }
+        }
+    }
+
+    // Sends the next queued message in the current sending slot, or
+    // drop cover if there is none
+    async fn use_send_slot(&mut self, server_tx: &QueueSender<ServerCommand>) {
+        let online = self.online();
+        let Some(send_queue) = self.send_queue.as_mut() else {
+            return;
+        };
+        let now = Instant::now();
+        // Slots pass unused while the client is offline, its queued
+        // messages waiting for it to come back
+        if !online {
+            if let Some(queued) = send_queue.take_slot(now) {
+                send_queue.requeue(queued);
+            }
+            return;
+        }
+        // The remaining fragments of a message take the slots that
+        // follow its first one
+        if let Some(fragment) = send_queue.take_fragment(now) {
+            if let Err(e) = server_tx.send(ServerCommand::Send(fragment)).await {
+                eprintln!("[CLIENT][{}] Failed sending fragment: {e}", &self.id);
+            }
+            self.record_slot(SlotKind::Real);
+            return;
+        }
+        let slot_kind = match send_queue.take_slot(now) {
+            Some(queued) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics
+                        .send_queue_delay
+                        .get_or_create(&NodeLabels {
+                            node: self.id.clone(),
+                        })
+                        .observe(now.duration_since(queued.queued_at).as_secs_f64());
+                }
+                self.send_queued(queued, server_tx).await;
+                SlotKind::Real
+            }
+            None => {
+                self.send_cover(CoverKind::Drop, server_tx).await;
+                SlotKind::Dummy
+            }
+        };
+        let queue_length = self.send_queue.as_ref().map_or(0, SendQueue::len);
+        self.record_queue_length(queue_length);
+        self.record_slot(slot_kind);
+    }
+
+    fn record_slot(&self, kind: SlotKind) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .send_slots
+                .get_or_create(&SlotLabels {
+                    client: self.id.clone(),
+                    kind,
+                })
+                .inc();
+        }
+    }
+
+    // Sends a message that has waited for its slot; it is put back at
+    // the front of the queue if the client does not know enough nodes
+    // to route it yet
+    async fn send_queued(&mut self, queued: QueuedMessage, server_tx: &QueueSender<ServerCommand>) {
+        let path_length = queued.path_length.unwrap_or(self.path_length);
+        if !self.knows_enough_nodes(&queued.to, path_length) {
+            self.refresh_address_book().await;
+            if !self.knows_enough_nodes(&queued.to, path_length) {
+                if let Some(send_queue) = self.send_queue.as_mut() {
+                    send_queue.requeue(queued);
+                }
+                return;
+            }
+        }
+        let to = queued.to;
+        if !self.address_book.contains_key(&to) && !self.fetch_registration(&to).await {
+            self.stats.record_dropped(PacketKind::Message);
+            return;
+        }
+        let route = self.select_route(&to, path_length);
+        let Some(recipient) = self.address_book.get(&to).cloned() else {
+            return;
+        };
+        println!(
+            "[CLIENT][{}] Sending message through: {}",
+            &self.id,
+            route_string(&route, &to)
+        );
+        let mut packets = match self.message_packets(
+            &route,
+            &recipient,
+            queued.body,
+            queued.path_length,
+            queued.sent_at_micros,
+            queued.id,
+        ) {
+            Ok(packets) => packets.into_iter(),
+            Err(e) => {
+                eprintln!(
+                    "[CLIENT][{}] Failed to wrap message to \"{to}\" in packets: {e}",
+                    &self.id
+                );
+                self.stats.record_dropped(PacketKind::Message);
+                return;
+            }
+        };
+        let Some(packet) = packets.next() else {
+            return;
+        };
+        if let Some(send_queue) = self.send_queue.as_mut() {
+            send_queue.defer(packets);
+        }
+        let kind = packet.kind();
+        if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+            eprintln!(
+                "[CLIENT][{}] Failed sending message to \"{to}\": {e}",
+                &self.id
+            );
+            self.stats.record_dropped(kind);
+        }
+    }
+
+    // Wraps a message from the user in Sphinx packets, asking for an
+    // ACK and waiting for it if the client uses acknowledgements, and
+    // attaching a SURB if it uses SURBs
+    fn message_packets(
+        &mut self,
+        route: &[DirectoryRegistration],
+        recipient: &DirectoryRegistration,
+        body: String,
+        path_length: Option<usize>,
+        sent_at_micros: u64,
+        message_id: u64,
+    ) -> Result<Vec<Packet>, ClientSendError> {
+        // Anonymous messages cannot be acknowledged, as the recipient
+        // does not know whom to send the ACK to
+        let anonymous = self
+            .reply_blocks
+            .as_ref()
+            .is_some_and(ReplyBlocks::anonymous);
+        let id = match anonymous {
+            true => None,
+            false => self.acks.as_mut().map(AckTracker::new_id),
+        };
+        let surb = self.attach_surb(&recipient.id, path_length.unwrap_or(self.path_length));
+        let message = Message {
+            from: (!anonymous).then(|| self.id.clone()),
+            body,
+            sent_at_micros,
+            kind: match id {
+                Some(_) => PacketKind::ReliableMessage,
+                None => PacketKind::Message,
+            },
+            surb,
+            fragment: None,
+        };
+        let packets = self.wrap(
+            route,
+            recipient,
+            &message,
+            path_length.unwrap_or(self.path_length),
+            id.unwrap_or_default(),
+        )?;
+        if let (Some(acks), Some(id)) = (self.acks.as_mut(), id) {
+            acks.track(id, message_id, &recipient.id, &message, path_length);
+        }
+        Ok(packets)
+    }
+
+    // Wraps a message in a single Sphinx packet over `route` if it fits
+    // in one, or splits it into fragments otherwise, the first taking
+    // `route` and every other one a route of its own; all of them carry
+    // the same identifier
+    fn wrap(
+        &mut self,
+        route: &[DirectoryRegistration],
+        recipient: &DirectoryRegistration,
+        message: &Message,
+        path_length: usize,
+        identifier: [u8; 16],
+    ) -> Result<Vec<Packet>, ClientSendError> {
+        let fragments = match self.fragmenter.as_mut() {
+            Some(fragmenter)
+                if fragmenter.erasure_coded() || !self.sphinx_builder.fits(message) =>
+            {
+                fragmenter.split(serde_yaml::to_string(message).unwrap().as_bytes())?
+            }
+            _ => {
+                let packet = self
+                    .sphinx_builder
+                    .build_with_identifier(route, recipient, message, identifier)?;
+                self.stats.record_packets(1);
+                return Ok(vec![packet]);
+            }
+        };
+        let (id, fragments) = fragments;
+        let count = fragments.len();
+        let needed = fragments.first().map_or(count, |fragment| fragment.needed);
+        if needed < count {
+            println!(
+                "[CLIENT][{}] Encoding message to \"{}\" into {count} fragments, any {needed} of which recover it",
+                &self.id, &recipient.id
+            );
+        } else {
+            println!(
+                "[CLIENT][{}] Message to \"{}\" is too large for a single packet, splitting it into {count} fragments",
+                &self.id, &recipient.id
+            );
+        }
+        // Fragments take disjoint routes as far as there are nodes to,
+        // so that no single node sees more than one of them
+        let mut used = route
+            .iter()
+            .map(|entry| entry.id.clone())
+            .collect::<HashSet<String>>();
+        let mut packets = Vec::with_capacity(count);
+        for fragment in fragments {
+            let route = match fragment.index {
+                0 => route.to_vec(),
+                index => {
+                    let route = self.select_route_avoiding(&recipient.id, path_length, &used);
+                    used.extend(route.iter().map(|entry| entry.id.clone()));
+                    println!(
+                        "[CLIENT][{}] Sending fragment {} of {count} through: {}",
+                        &self.id,
+                        index + 1,
+                        route_string(&route, &recipient.id)
+                    );
+                    route
+                }
+            };
+            let fragment_message = Message {
+                from: None,
+                body: String::new(),
+                sent_at_micros: message.sent_at_micros,
+                kind: PacketKind::Fragment(id),
+                surb: None,
+                fragment: Some(fragment),
+            };
+            packets.push(self.sphinx_builder.build_with_identifier(
+                &route,
+                recipient,
+                &fragment_message,
+                identifier,
+            )?);
+        }
+        // Only plain messages count towards the summary as a whole;
+        // reliable ones are accounted for by their ACKs
+        if message.kind.is_message() {
+            self.stats.record_fragmented(id, needed, count);
+        }
+        self.stats.record_packets(count);
+        self.record_fragments(FragmentStatus::Sent, count);
+        Ok(packets)
+    }
+
+    // Adds a received fragment to the message it belongs to, returning
+    // that message once enough of its fragments have arrived
+    fn receive_fragment(&mut self, id: u64, fragment: Option<Fragment>) -> Option<Message> {
+        let (Some(fragmenter), Some(fragment)) = (self.fragmenter.as_mut(), fragment) else {
+            eprintln!(
+                "[CLIENT][{}] Received fragment it cannot put back together",
+                &self.id
+            );
+            return None;
+        };
+        let (needed, count) = (fragment.needed, fragment.count);
+        let bytes = fragmenter.add(id, fragment, Instant::now());
+        self.record_fragments(FragmentStatus::Received, 1);
+        let bytes = bytes?;
+        match serde_yaml::from_slice::<Message>(&bytes) {
+            Ok(message) => {
+                println!(
+                    "[CLIENT][{}] Put message back together from {needed} of {count} fragments",
+                    &self.id
+                );
+                self.record_reassembly(ReassemblyStatus::Completed);
+                self.stats.record_reassembled(id);
+                Some(message)
+            }
+            Err(e) => {
+                eprintln!(
+                    "[CLIENT][{}] Failed to put message back together from {count} fragments: {e}",
+                    &self.id
+                );
+                self.stats.record_abandoned(id);
+                None
+            }
+        }
+    }
+
+    // Gives up on every fragmented message of which too few fragments
+    // have arrived in time
+    fn check_reassembly(&mut self) {
+        let Some(fragmenter) = self.fragmenter.as_mut() else {
+            return;
+        };
+        for abandoned in fragmenter.take_expired(Instant::now()) {
+            eprintln!(
+                "[CLIENT][{}] Giving up on fragmented message after receiving {} of the {} of {} fragments needed",
+                &self.id, abandoned.received, abandoned.needed, abandoned.count
+            );
+            self.record_reassembly(ReassemblyStatus::TimedOut);
+            self.stats.record_abandoned(abandoned.id);
+            self.record_fragments(
+                FragmentStatus::Lost,
+                abandoned.count.saturating_sub(abandoned.received),
+            );
+        }
+    }
+
+    fn record_fragments(&self, status: FragmentStatus, count: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .fragments
+                .get_or_create(&FragmentLabels {
+                    client: self.id.clone(),
+                    status,
+                })
+                .inc_by(count as u64);
+        }
+    }
+
+    fn record_reassembly(&self, status: ReassemblyStatus) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .reassemblies
+                .get_or_create(&ReassemblyLabels {
+                    client: self.id.clone(),
+                    status,
+                })
+                .inc();
+        }
+    }
+
+    // Builds a SURB for a message to `to`, leading back to the client
+    // over a route of its own; the message is sent without one if the
+    // SURB cannot be built
+    fn attach_surb(&mut self, to: &str, path_length: usize) -> Option<String> {
+        let reply_id = self.reply_blocks.as_mut()?.await_reply(to);
+        let id = self.id.clone();
+        let route = self.select_route(&id, path_length);
+        let registration = self.registration();
+        match self
+            .sphinx_builder
+            .build_surb(&route, &registration, reply_id)
+        {
+            Ok(surb) => {
+                self.record_surb(SurbStatus::Attached);
+                Some(BASE64.encode(surb.to_bytes()))
+            }
+            Err(e) => {
+                eprintln!(
+                    "[CLIENT][{}] Failed to construct SURB for message to \"{to}\": {e}",
+                    &self.id
+                );
+                if let Some(reply_blocks) = self.reply_blocks.as_mut() {
+                    reply_blocks.take_awaiting(&reply_id);
+                }
+                self.record_surb(SurbStatus::Failed);
+                None
+            }
+        }
+    }
+
+    // Keeps the SURB of a received message for the user to reply
+    // through, returning its handle; SURBs are ignored by clients that
+    // do not use them
+    fn receive_surb(&mut self, surb: &str) -> Option<u64> {
+        self.reply_blocks.as_ref()?;
+        let Some(surb) = BASE64
+            .decode(surb)
+            .ok()
+            .and_then(|bytes| SURB::from_bytes(&bytes).ok())
+        else {
+            eprintln!(
+                "[CLIENT][{}] Failed to decode SURB of received message",
+                &self.id
+            );
+            self.record_surb(SurbStatus::Failed);
+            return None;
+        };
+        let handle = self.reply_blocks.as_mut()?.store(surb);
+        self.record_surb(SurbStatus::Received);
+        Some(handle)
+    }
+
+    // Sends a reply through the SURB with the given handle, which can
+    // only be used once
+    async fn reply(
+        &mut self,
+        surb: u64,
+        body: String,
+        server_tx: &QueueSender<ServerCommand>,
+    ) -> Result<(), ClientSendError> {
+        let message = Message {
+            from: None,
+            body,
+            sent_at_micros: self.stats.elapsed().as_micros() as u64,
+            kind: PacketKind::Reply,
+            surb: None,
+            fragment: None,
+        };
+        // A SURB carries a single packet, so replies are never
+        // fragmented; one too large is refused before using up the SURB
+        if !self.sphinx_builder.fits(&message) {
+            eprintln!(
+                "[CLIENT][{}] Refusing reply of {} bytes, which does not fit in a single packet",
+                &self.id,
+                message.body.len()
+            );
+            return Err(ClientSendError::ReplyTooLarge);
+        }
+        let Some(surb) = self
+            .reply_blocks
+            .as_mut()
+            .and_then(|reply_blocks| reply_blocks.take(surb))
+        else {
+            eprintln!(
+                "[CLIENT][{}] Failed to reply, SURB {surb} is unknown or already used",
+                &self.id
+            );
+            self.record_surb(SurbStatus::Failed);
+            return Err(ClientSendError::SurbUnavailable);
+        };
+        let packet = match self.sphinx_builder.build_reply(surb, &message) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!(
+                    "[CLIENT][{}] Failed to construct reply through SURB: {e}",
+                    &self.id
+                );
+                self.record_surb(SurbStatus::Failed);
+                return Err(ClientSendError::InvalidSurb);
+            }
+        };
+        println!(
+            "[CLIENT][{}] Sending reply through SURB via \"{}\"",
+            &self.id,
+            packet.to()
+        );
+        if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+            eprintln!("[CLIENT][{}] Failed sending reply: {e}", &self.id);
+            self.record_surb(SurbStatus::Failed);
+            return Err(ClientSendError::from(e));
+        }
+        self.record_surb(SurbStatus::Used);
+        Ok(())
+    }
+
+    // Handles a reply that came back through one of the client's SURBs
+    async fn receive_reply(&mut self, id: [u8; 16], body: String) {
+        let Some(to) = self
+            .reply_blocks
+            .as_mut()
+            .and_then(|reply_blocks| reply_blocks.take_awaiting(&id))
+        else {
+            eprintln!(
+                "[CLIENT][{}] Received reply that no message was waiting for",
+                &self.id
+            );
+            self.record_surb(SurbStatus::Failed);
+            return;
+        };
+        println!(
+            "[CLIENT][{}] Received reply to message to \"{to}\": {body}",
+            &self.id
+        );
+        self.record_surb(SurbStatus::Returned);
+        self.hand_to_user(ReceivedMessage {
+            from: Some(to),
+            body,
+            surb: None,
+        })
+        .await;
+    }
+
+    // Passes a received message on to the user, if the user listens
+    // for them
+    async fn hand_to_user(&mut self, message: ReceivedMessage) {
+        let Some(inbox_tx) = &self.inbox_tx else {
+            return;
+        };
+        if let Err(e) = inbox_tx.send(message).await {
+            eprintln!(
+                "[CLIENT][{}] Failed to hand received message to user: {e}",
+                &self.id
+            );
+        }
+    }
+
+    fn record_surb(&self, status: SurbStatus) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .surbs
+                .get_or_create(&SurbLabels {
+                    client: self.id.clone(),
+                    status,
+                })
+                .inc();
+        }
+    }
+
+    // Retransmits every message whose ACK is overdue, or gives up on it
+    // once it has been sent as many times as allowed
+    async fn check_acks(&mut self, server_tx: &QueueSender<ServerCommand>) {
+        let Some(acks) = self.acks.as_mut() else {
+            return;
+        };
+        for (id, message) in acks.take_overdue(Instant::now()) {
+            if self
+                .acks
+                .as_ref()
+                .is_some_and(|acks| acks.may_retry(message.attempts))
+            {
+                self.retransmit(id, message, server_tx).await;
+            } else {
+                eprintln!(
+                    "[CLIENT][{}] Giving up on message to \"{}\" after {} attempts",
+                    &self.id, &message.to, message.attempts
+                );
+                self.stats.record_given_up(id);
+                self.report_delivery(&message, DeliveryStatus::Failed(message.attempts))
+                    .await;
+            }
+        }
+    }
+
+    // Sends a message again over a fresh route; the attempt counts even
+    // if no route can be built yet
+    async fn retransmit(
+        &mut self,
+        id: [u8; 16],
+        mut pending: PendingMessage,
+        server_tx: &QueueSender<ServerCommand>,
+    ) {
+        pending.attempts += 1;
+        let path_length = pending.path_length.unwrap_or(self.path_length);
+        if !self.knows_enough_nodes(&pending.to, path_length) {
+            self.refresh_address_book().await;
+        }
+        let recipient = self.address_book.get(&pending.to).cloned();
+        if let (true, Some(recipient)) = (
+            self.online() && self.knows_enough_nodes(&pending.to, path_length),
+            recipient,
+        ) {
+            let route = self.select_route(&pending.to, path_length);
+            println!(
+                "[CLIENT][{}] Retransmitting message through: {} (attempt {})",
+                &self.id,
+                route_string(&route, &pending.to),
+                pending.attempts
+            );
+            let message = Message {
+                from: Some(self.id.clone()),
+                body: pending.body.clone(),
+                sent_at_micros: pending.sent_at_micros,
+                kind: PacketKind::ReliableMessage,
+                surb: pending.surb.clone(),
+                fragment: None,
+            };
+            match self.wrap(&route, &recipient, &message, path_length, id) {
+                Ok(packets) => {
+                    for packet in packets {
+                        if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+                            eprintln!(
+                                "[CLIENT][{}] Failed retransmitting message to \"{}\": {e}",
+                                &self.id, &pending.to
+                            );
+                            break;
                         }
                     }
                 }
+                Err(e) => eprintln!(
+                    "[CLIENT][{}] Failed to wrap message to \"{}\" in packets: {e}",
+                    &self.id, &pending.to
+                ),
             }
         }
+        if let Some(acks) = self.acks.as_mut() {
+            acks.retrack(id, pending);
+        }
     }
 
-    pub fn get_tx(&self) -> MpscSender<ClientCommand> {
-        self.client_tx.clone()
+    // Acknowledges the message with the given id to its sender
+    async fn send_ack(&mut self, to: &str, id: [u8; 16], server_tx: &QueueSender<ServerCommand>) {
+        if !self.knows_enough_nodes(to, self.path_length) {
+            self.refresh_address_book().await;
+        }
+        if !self.knows_enough_nodes(to, self.path_length)
+            || (!self.address_book.contains_key(to) && !self.fetch_registration(to).await)
+        {
+            return;
+        }
+        let Some(recipient) = self.address_book.get(to).cloned() else {
+            return;
+        };
+        let route = self.select_route(to, self.path_length);
+        println!(
+            "[CLIENT][{}] Sending ACK through: {}",
+            &self.id,
+            route_string(&route, to)
+        );
+        let message = Message {
+            from: None,
+            body: String::new(),
+            sent_at_micros: self.stats.elapsed().as_micros() as u64,
+            kind: PacketKind::Ack,
+            surb: None,
+            fragment: None,
+        };
+        match self
+            .sphinx_builder
+            .build_with_identifier(&route, &recipient, &message, id)
+        {
+            Ok(packet) => {
+                if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+                    eprintln!("[CLIENT][{}] Failed sending ACK to \"{to}\": {e}", &self.id);
+                }
+            }
+            Err(e) => eprintln!(
+                "[CLIENT][{}] Failed to construct ACK to \"{to}\": {e}",
+                &self.id
+            ),
+        }
+    }
+
+    async fn receive_ack(&mut self, id: [u8; 16]) {
+        let Some(pending) = self.acks.as_mut().and_then(|acks| acks.acknowledge(&id)) else {
+            return;
+        };
+        println!(
+            "[CLIENT][{}] Message to \"{}\" acknowledged after {} attempts",
+            &self.id, &pending.to, pending.attempts
+        );
+        self.report_delivery(&pending, DeliveryStatus::Delivered(pending.attempts))
+            .await;
+    }
+
+    async fn report_delivery(&mut self, message: &PendingMessage, status: DeliveryStatus) {
+        let Some(delivery_tx) = &self.delivery_tx else {
+            return;
+        };
+        let report = DeliveryReport {
+            id: message.message_id,
+            to: message.to.clone(),
+            status,
+        };
+        if let Err(e) = delivery_tx.send(report).await {
+            eprintln!(
+                "[CLIENT][{}] Failed to report delivery status of message to \"{}\": {e}",
+                &self.id, &message.to
+            );
+        }
+    }
+
+    fn record_sent(&self, to: &str) {
+        self.stats.record_sent();
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .messages
+                .get_or_create(&MessageLabels {
+                    from: self.id.clone(),
+                    to: to.to_owned(),
+                    status: MessageStatus::Sent,
+                })
+                .inc();
+        }
+    }
+
+    fn record_queue_length(&self, queue_length: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .send_queue_length
+                .get_or_create(&NodeLabels {
+                    node: self.id.clone(),
+                })
+                .set(queue_length as i64);
+        }
+    }
+
+    // The client itself for loop cover, or a random other client for
+    // drop cover
+    fn cover_recipient(&mut self, kind: CoverKind) -> Option<DirectoryRegistration> {
+        match kind {
+            CoverKind::Loop => Some(self.registration()),
+            CoverKind::Drop => {
+                let mut clients = self
+                    .address_book
+                    .values()
+                    .filter(|entry| entry.role == NodeRole::Client)
+                    .collect::<Vec<&DirectoryRegistration>>();
+                clients.sort_by(|a, b| a.id.cmp(&b.id));
+                clients
+                    .choose(&mut self.routing_rng)
+                    .map(|&entry| entry.clone())
+            }
+        }
+    }
+
+    // Sends a cover packet, either looping back to the client or to a
+    // random other client; cover is skipped while the client does not
+    // know enough nodes to build a route
+    async fn send_cover(&mut self, kind: CoverKind, server_tx: &QueueSender<ServerCommand>) {
+        if !self.online() {
+            return;
+        }
+        let mut recipient = self.cover_recipient(kind);
+        if !recipient
+            .as_ref()
+            .is_some_and(|recipient| self.knows_enough_nodes(&recipient.id, self.path_length))
+        {
+            self.refresh_address_book().await;
+            recipient = self.cover_recipient(kind);
+        }
+        let Some(recipient) =
+            recipient.filter(|recipient| self.knows_enough_nodes(&recipient.id, self.path_length))
+        else {
+            return;
+        };
+        let route = self.select_route(&recipient.id, self.path_length);
+        println!(
+            "[CLIENT][{}] Sending {kind:?} cover through: {}",
+            &self.id,
+            route_string(&route, &recipient.id)
+        );
+        let message = Message {
+            from: None,
+            body: String::new(),
+            sent_at_micros: self.stats.elapsed().as_micros() as u64,
+            kind: PacketKind::Cover(kind),
+            surb: None,
+            fragment: None,
+        };
+        let packet = match self.sphinx_builder.build(&route, &recipient, &message) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!(
+                    "[CLIENT][{}] Failed to construct {kind:?} cover packet: {e}",
+                    &self.id
+                );
+                return;
+            }
+        };
+        if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+            eprintln!(
+                "[CLIENT][{}] Failed sending {kind:?} cover packet: {e}",
+                &self.id
+            );
+            return;
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .cover
+                .get_or_create(&CoverLabels {
+                    client: self.id.clone(),
+                    kind,
+                    status: MessageStatus::Sent,
+                })
+                .inc();
+        }
+    }
+
+    // Handles a cover packet the client is the destination of; loop
+    // cover has come back around, drop cover is discarded
+    fn receive_cover(&self, kind: CoverKind) {
+        println!("[CLIENT][{}] Received {kind:?} cover", &self.id);
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .cover
+                .get_or_create(&CoverLabels {
+                    client: self.id.clone(),
+                    kind,
+                    status: MessageStatus::Received,
+                })
+                .inc();
+        }
+    }
+
+    fn online(&self) -> bool {
+        self.churn.as_ref().is_none_or(Churn::online)
+    }
+
+    // Moves on to a fresh Sphinx key and publishes it in the directory
+    async fn rotate_keys(&mut self) {
+        let cleared = self.keys.rotate(Instant::now());
+        println!(
+            "[CLIENT][{}] Rotated keys, clearing {cleared} replay tags",
+            &self.id
+        );
+        rekey_node(
+            "[CLIENT]",
+            &self.id,
+            self.keys.public_key(),
+            &self.directory_tx,
+        )
+        .await;
+    }
+
+    fn record_replay(&self, status: ReplayStatus) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .replays
+                .get_or_create(&ReplayLabels {
+                    node: self.id.clone(),
+                    status,
+                })
+                .inc();
+        }
+    }
+
+    // Takes the client offline or brings it back, leaving the
+    // directory while offline if its availability model says to
+    async fn change_availability(&mut self) {
+        let Some(churn) = self.churn.as_mut() else {
+            return;
+        };
+        let online = churn.change(Instant::now());
+        let deregisters = churn.deregisters();
+        self.forwarder.set_online(online);
+        self.record_online();
+        if online {
+            println!("[CLIENT][{}] Back online", &self.id);
+            if deregisters {
+                register_in_directory("[CLIENT]", self.registration(), &self.directory_tx).await;
+            }
+        } else {
+            println!("[CLIENT][{}] Going offline", &self.id);
+            if deregisters {
+                deregister_node("[CLIENT]", &self.id, &self.directory_tx).await;
+            }
+        }
+    }
+
+    fn record_online(&self) {
+        if let (Some(metrics), Some(churn)) = (&self.metrics, &self.churn) {
+            metrics
+                .online
+                .get_or_create(&NodeLabels {
+                    node: self.id.clone(),
+                })
+                .set(churn.online() as i64);
+        }
+    }
+
+    // Whether the address book holds enough nodes to route towards `to`
+    // through; free routes need as many candidates as there are hops,
+    // layered routes need a mix node in every configured layer
+    fn knows_enough_nodes(&self, to: &str, path_length: usize) -> bool {
+        match self.topology {
+            Topology::FreeRoute => {
+                route_candidates(&self.address_book, &self.id, to).len() >= path_length
+            }
+            Topology::Stratified | Topology::Cascade => {
+                let layers = mix_layers(&self.address_book, self.layers);
+                !layers.is_empty() && layers.iter().all(|layer| !layer.is_empty())
+            }
+        }
+    }
+
+    // Picks the intermediate hops of a route towards `to`; the path
+    // length only applies to free routes, layered routes take one node
+    // per layer, and either is followed by the recipient's provider if
+    // it has one
+    fn select_route(&mut self, to: &str, path_length: usize) -> Vec<DirectoryRegistration> {
+        self.select_route_avoiding(to, path_length, &HashSet::new())
+    }
+
+    // Picks a route as `select_route` does, but not through any of the
+    // nodes in `avoid` unless there are too few others to route through
+    fn select_route_avoiding(
+        &mut self,
+        to: &str,
+        path_length: usize,
+        avoid: &HashSet<String>,
+    ) -> Vec<DirectoryRegistration> {
+        let mut route = match self.topology {
+            Topology::FreeRoute => {
+                let candidates = avoiding(
+                    route_candidates(&self.address_book, &self.id, to),
+                    avoid,
+                    path_length,
+                );
+                self.route_selector
+                    .select(&candidates, path_length, &mut self.routing_rng)
+            }
+            Topology::Stratified => mix_layers(&self.address_book, self.layers)
+                .into_iter()
+                .flat_map(|layer| {
+                    let layer = avoiding(layer, avoid, 1);
+                    self.route_selector.select(&layer, 1, &mut self.routing_rng)
+                })
+                .collect(),
+            Topology::Cascade => {
+                // The n-th cascade is made up of the n-th node of every
+                // layer, so there are as many as the smallest layer has
+                // nodes; a cascade is picked by its entry node
+                let layers = mix_layers(&self.address_book, self.layers);
+                let cascades = layers.iter().map(Vec::len).min().unwrap_or_default();
+                if cascades == 0 {
+                    return vec![];
+                }
+                let entries = avoiding(layers[0][..cascades].to_vec(), avoid, 1);
+                let entries = self
+                    .route_selector
+                    .select(&entries, 1, &mut self.routing_rng);
+                match entries
+                    .first()
+                    .and_then(|entry| layers[0].iter().position(|node| node.id == entry.id))
+                {
+                    Some(cascade) => layers.iter().map(|layer| layer[cascade].clone()).collect(),
+                    None => vec![],
+                }
+            }
+        };
+        self.route_selector.record(&route);
+
+        // Routes to a client with a mailbox end at its provider, which
+        // for loop cover is the client's own
+        let provider = if to == self.id {
+            self.mailbox.as_ref().map(|mailbox| &mailbox.provider)
+        } else {
+            self.address_book
+                .get(to)
+                .and_then(|entry| entry.provider.as_ref())
+        };
+        if let Some(provider) = provider {
+            match self.address_book.get(provider) {
+                Some(entry) => route.push(entry.clone()),
+                None => eprintln!(
+                    "[CLIENT][{}] Provider \"{provider}\" of \"{to}\" is not in address book, sending directly",
+                    &self.id
+                ),
+            }
+        }
+        route
+    }
+}
+
+// Nodes a free route from `id` towards `to` may pass through: the mix
+// nodes if there are any, otherwise the clients other than the sender
+// and the recipient; sorted so that the seeded choice does not depend
+// on the address book's hash order
+fn route_candidates<'a>(
+    address_book: &'a HashMap<String, DirectoryRegistration>,
+    id: &str,
+    to: &str,
+) -> Vec<&'a DirectoryRegistration> {
+    let has_mix_nodes = address_book
+        .values()
+        .any(|entry| matches!(entry.role, NodeRole::Mix { .. }));
+    let mut candidates = address_book
+        .values()
+        .filter(|&entry| match entry.role {
+            NodeRole::Client => !has_mix_nodes && entry.id != to && entry.id != id,
+            NodeRole::Mix { .. } => true,
+            NodeRole::Provider => false,
+        })
+        .collect::<Vec<&DirectoryRegistration>>();
+    candidates.sort_by(|a, b| a.id.cmp(&b.id));
+    candidates
+}
+
+// Leaves out the candidates in `avoid`, unless fewer than `count`
+// would be left
+fn avoiding<'a>(
+    candidates: Vec<&'a DirectoryRegistration>,
+    avoid: &HashSet<String>,
+    count: usize,
+) -> Vec<&'a DirectoryRegistration> {
+    if avoid.is_empty() {
+        return candidates;
+    }
+    let remaining = candidates
+        .iter()
+        .copied()
+        .filter(|entry| !avoid.contains(&entry.id))
+        .collect::<Vec<&DirectoryRegistration>>();
+    match remaining.len() >= count {
+        true => remaining,
+        false => candidates,
+    }
+}
+
+// Lists the hops of a route for logging
+fn route_string(route: &[DirectoryRegistration], to: &str) -> String {
+    route.iter().fold(String::new(), |acc, entry| {
+        acc + &format!("{} -> ", &entry.id)
+    }) + to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn refuses_reply_larger_than_one_packet() {
+        let (directory_tx, _directory_rx) = mpsc::channel(1);
+        let (server_tx, _server_rx) = queue::channel::<ServerCommand>(1);
+        let mut client = Client::new(
+            "alice",
+            directory_tx,
+            1,
+            &None,
+            &Rngs::new(Some(1)),
+            Stats::new(None),
+            &Network::default(),
+        );
+        assert!(matches!(
+            client.reply(0, "x".repeat(2000), &server_tx).await,
+            Err(ClientSendError::ReplyTooLarge)
+        ));
+        // A reply that fits only fails for want of a SURB
+        assert!(matches!(
+            client.reply(0, "x".repeat(100), &server_tx).await,
+            Err(ClientSendError::SurbUnavailable)
+        ));
     }
 }
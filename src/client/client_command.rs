@@ -1,10 +1,32 @@
 use tokio::sync::mpsc::Sender as MpscSender;
 
-use crate::{client::ClientSendError, packet::Packet};
+use crate::{client::ClientSendError, packet::Packet, queue::Droppable};
 
 pub enum ClientCommand {
     Register,
     ReceivePacket(Packet),
-    Send(String, String, MpscSender<Result<(), ClientSendError>>),
+    // Recipient, body, and optionally the number of hops to route over;
+    // answered with the id that the client's delivery reports on the
+    // message carry
+    Send(
+        String,
+        String,
+        Option<usize>,
+        MpscSender<Result<u64, ClientSendError>>,
+    ),
+    // Handle of the SURB of a received message, and the body of the
+    // reply to send through it
+    Reply(u64, String, MpscSender<Result<(), ClientSendError>>),
+    // Fetches the client's mailbox from its provider, if it has one
+    FetchMailbox,
     Shutdown,
 }
+
+impl Droppable for ClientCommand {
+    fn packet(&self) -> Option<&Packet> {
+        match self {
+            ClientCommand::ReceivePacket(packet) => Some(packet),
+            _ => None,
+        }
+    }
+}
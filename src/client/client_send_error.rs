@@ -1,17 +1,38 @@
 use std::{error::Error, fmt::Display};
 
+use sphinx_packet::Error as SphinxError;
 use tokio::sync::mpsc::error::SendError;
 
-use crate::server::ServerCommand;
+use crate::{client::FragmentError, server::ServerCommand};
 
 #[derive(Debug)]
 pub enum ClientSendError {
     ServerChannelClosed,
+    MessageLimitReached,
+    // The SURB to reply through was never received or already used
+    SurbUnavailable,
+    InvalidSurb,
+    // The reply does not fit in the single packet a SURB carries
+    ReplyTooLarge,
+    // The client is offline according to its availability model
+    Offline,
+    // Too few nodes are registered to route the message through
+    NotEnoughNodes,
+    // The recipient is neither in the address book nor the directory
+    UnknownRecipient,
+    // The message could not be wrapped in Sphinx packets for its route
+    PacketConstruction(SphinxError),
+    // The message is too large to be split into fragments
+    MessageTooLarge(FragmentError),
 }
 
 impl Error for ClientSendError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+        match self {
+            ClientSendError::PacketConstruction(e) => Some(e),
+            ClientSendError::MessageTooLarge(e) => Some(e),
+            _ => None,
+        }
     }
 }
 
@@ -21,10 +42,49 @@ impl Display for ClientSendError {
             ClientSendError::ServerChannelClosed => {
                 write!(f, "server channel closed")
             }
+            ClientSendError::MessageLimitReached => {
+                write!(f, "run has reached its message limit")
+            }
+            ClientSendError::SurbUnavailable => {
+                write!(f, "no unused SURB to reply through")
+            }
+            ClientSendError::InvalidSurb => {
+                write!(f, "SURB could not be used to build a reply")
+            }
+            ClientSendError::ReplyTooLarge => {
+                write!(f, "reply does not fit in a single packet")
+            }
+            ClientSendError::Offline => {
+                write!(f, "client is offline")
+            }
+            ClientSendError::NotEnoughNodes => {
+                write!(f, "too few nodes registered to route through")
+            }
+            ClientSendError::UnknownRecipient => {
+                write!(f, "recipient is not registered in the directory")
+            }
+            ClientSendError::PacketConstruction(e) => {
+                write!(f, "failed to construct Sphinx packet: {e}")
+            }
+            ClientSendError::MessageTooLarge(e) => {
+                write!(f, "message is too large to send: {e}")
+            }
         }
     }
 }
 
+impl From<SphinxError> for ClientSendError {
+    fn from(e: SphinxError) -> Self {
+        ClientSendError::PacketConstruction(e)
+    }
+}
+
+impl From<FragmentError> for ClientSendError {
+    fn from(e: FragmentError) -> Self {
+        ClientSendError::MessageTooLarge(e)
+    }
+}
+
 impl From<SendError<ServerCommand>> for ClientSendError {
     fn from(_: SendError<ServerCommand>) -> Self {
         ClientSendError::ServerChannelClosed
@@ -0,0 +1,66 @@
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Exp};
+use tokio::time::{Duration, Instant};
+
+use crate::{config::Cover, packet::CoverKind};
+
+// When a client next sends each kind of cover, each kind being sent
+// as an independent Poisson process
+pub struct CoverTraffic {
+    loop_rate: Option<Exp<f64>>,
+    drop_rate: Option<Exp<f64>>,
+    next_loop: Option<Instant>,
+    next_drop: Option<Instant>,
+    rng: StdRng,
+}
+
+impl CoverTraffic {
+    pub fn new(cover: &Cover, rng: StdRng) -> Self {
+        let rate = |rate: Option<f64>| {
+            rate.filter(|rate| *rate > 0.0)
+                .and_then(|rate| Exp::new(rate).ok())
+        };
+        let mut cover = Self {
+            loop_rate: rate(cover.loop_rate_per_second),
+            drop_rate: rate(cover.drop_rate_per_second),
+            next_loop: None,
+            next_drop: None,
+            rng,
+        };
+        let now = Instant::now();
+        cover.next_loop = cover.schedule(CoverKind::Loop, now);
+        cover.next_drop = cover.schedule(CoverKind::Drop, now);
+        cover
+    }
+
+    // When the next cover of either kind is due, if ever
+    pub fn next_due(&self) -> Option<Instant> {
+        match (self.next_loop, self.next_drop) {
+            (Some(next_loop), Some(next_drop)) => Some(next_loop.min(next_drop)),
+            (next_loop, next_drop) => next_loop.or(next_drop),
+        }
+    }
+
+    // Returns the kinds of cover due by `now`, scheduling the next
+    // cover of each of them
+    pub fn take_due(&mut self, now: Instant) -> Vec<CoverKind> {
+        let mut due = vec![];
+        if self.next_loop.is_some_and(|next_loop| next_loop <= now) {
+            due.push(CoverKind::Loop);
+            self.next_loop = self.schedule(CoverKind::Loop, now);
+        }
+        if self.next_drop.is_some_and(|next_drop| next_drop <= now) {
+            due.push(CoverKind::Drop);
+            self.next_drop = self.schedule(CoverKind::Drop, now);
+        }
+        due
+    }
+
+    fn schedule(&mut self, kind: CoverKind, now: Instant) -> Option<Instant> {
+        let rate = match kind {
+            CoverKind::Loop => self.loop_rate,
+            CoverKind::Drop => self.drop_rate,
+        }?;
+        Some(now + Duration::from_secs_f64(rate.sample(&mut self.rng)))
+    }
+}
@@ -0,0 +1,17 @@
+// What came of a message sent with acknowledgements on
+#[derive(Clone, Debug)]
+pub enum DeliveryStatus {
+    // Acknowledged after the given number of attempts
+    Delivered(u32),
+    // Not acknowledged after the given number of attempts
+    Failed(u32),
+}
+
+// Tells a user what came of one of its messages
+#[derive(Clone, Debug)]
+pub struct DeliveryReport {
+    // Id the client gave the message when the user sent it
+    pub id: u64,
+    pub to: String,
+    pub status: DeliveryStatus,
+}
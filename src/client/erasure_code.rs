@@ -0,0 +1,239 @@
+// Systematic Reed-Solomon code over GF(2^8): a message is cut into
+// `needed` data shards, followed by parity shards from the rows of a
+// Cauchy matrix, so that any `needed` of the `total` shards recover it
+pub struct ErasureCode {
+    needed: usize,
+    total: usize,
+}
+
+// Shards are told apart by their evaluation point in the field
+pub const MAX_SHARDS: usize = 256;
+
+// Exponent and logarithm tables for the field, built from the
+// polynomial x^8 + x^4 + x^3 + x^2 + 1 with generator 2
+const GF_TABLES: ([u8; 512], [u8; 256]) = gf_tables();
+
+const fn gf_tables() -> ([u8; 512], [u8; 256]) {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    (exp, log)
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    let (exp, log) = &GF_TABLES;
+    exp[log[a as usize] as usize + log[b as usize] as usize]
+}
+
+fn gf_inv(a: u8) -> u8 {
+    let (exp, log) = &GF_TABLES;
+    exp[255 - log[a as usize] as usize]
+}
+
+impl ErasureCode {
+    // Both counts are clamped to what the field can tell apart
+    pub fn new(needed: usize, total: usize) -> Self {
+        let needed = needed.clamp(1, MAX_SHARDS);
+        Self {
+            needed,
+            total: total.clamp(needed, MAX_SHARDS),
+        }
+    }
+
+    // Coefficient of data shard `column` in the shard at `row`; data
+    // shards map onto themselves, parity shards onto 1 / (x_row + y_column)
+    // with x_row = row and y_column = column, which never coincide
+    fn coefficient(&self, row: usize, column: usize) -> u8 {
+        if row < self.needed {
+            return u8::from(row == column);
+        }
+        gf_inv((row ^ column) as u8)
+    }
+
+    // Cuts `bytes` into `total` shards of equal length, padding the last
+    // data shard with zeros
+    pub fn encode(&self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let shard_len = bytes.len().div_ceil(self.needed).max(1);
+        let mut shards = (0..self.needed)
+            .map(|i| {
+                let mut shard = bytes
+                    .get(i * shard_len..bytes.len().min((i + 1) * shard_len))
+                    .unwrap_or_default()
+                    .to_vec();
+                shard.resize(shard_len, 0);
+                shard
+            })
+            .collect::<Vec<Vec<u8>>>();
+        for row in self.needed..self.total {
+            let mut parity = vec![0u8; shard_len];
+            for (column, shard) in shards[..self.needed].iter().enumerate() {
+                let coefficient = self.coefficient(row, column);
+                for (p, &b) in parity.iter_mut().zip(shard) {
+                    *p ^= gf_mul(coefficient, b);
+                }
+            }
+            shards.push(parity);
+        }
+        shards
+    }
+
+    // Recovers the padded bytes from the shards received, indexed by
+    // position, once at least `needed` of them are present
+    pub fn decode(&self, shards: &[Option<Vec<u8>>]) -> Option<Vec<u8>> {
+        let present = shards
+            .iter()
+            .enumerate()
+            .filter_map(|(row, shard)| shard.as_ref().map(|shard| (row, shard)))
+            .take(self.needed)
+            .collect::<Vec<(usize, &Vec<u8>)>>();
+        if present.len() < self.needed {
+            return None;
+        }
+        // With every data shard there is nothing to solve for
+        if present.iter().all(|&(row, _)| row < self.needed) {
+            return Some(
+                present
+                    .into_iter()
+                    .flat_map(|(_, shard)| shard.clone())
+                    .collect(),
+            );
+        }
+        let shard_len = present[0].1.len();
+        if present.iter().any(|(_, shard)| shard.len() != shard_len) {
+            return None;
+        }
+        let inverse = self.invert(present.iter().map(|&(row, _)| row))?;
+        let mut bytes = Vec::with_capacity(self.needed * shard_len);
+        for coefficients in inverse {
+            let mut data = vec![0u8; shard_len];
+            for (&coefficient, (_, shard)) in coefficients.iter().zip(&present) {
+                for (d, &b) in data.iter_mut().zip(shard.iter()) {
+                    *d ^= gf_mul(coefficient, b);
+                }
+            }
+            bytes.extend(data);
+        }
+        Some(bytes)
+    }
+
+    // Inverts the square matrix made of the rows at the given positions
+    // by Gauss-Jordan elimination
+    fn invert(&self, rows: impl Iterator<Item = usize>) -> Option<Vec<Vec<u8>>> {
+        let n = self.needed;
+        let mut matrix = rows
+            .map(|row| {
+                let mut augmented = (0..n)
+                    .map(|column| self.coefficient(row, column))
+                    .collect::<Vec<u8>>();
+                augmented.resize(2 * n, 0);
+                augmented
+            })
+            .collect::<Vec<Vec<u8>>>();
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[n + i] = 1;
+        }
+        for column in 0..n {
+            let pivot = (column..n).find(|&row| matrix[row][column] != 0)?;
+            matrix.swap(column, pivot);
+            let scale = gf_inv(matrix[column][column]);
+            for value in matrix[column].iter_mut() {
+                *value = gf_mul(*value, scale);
+            }
+            let pivot_row = matrix[column].clone();
+            for (row, values) in matrix.iter_mut().enumerate() {
+                let factor = values[column];
+                if row == column || factor == 0 {
+                    continue;
+                }
+                for (value, &p) in values.iter_mut().zip(&pivot_row) {
+                    *value ^= gf_mul(factor, p);
+                }
+            }
+        }
+        Some(matrix.into_iter().map(|row| row[n..].to_vec()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every way of picking `k` of the positions `0..n`
+    fn subsets(n: usize, k: usize) -> Vec<Vec<usize>> {
+        if k == 0 {
+            return vec![vec![]];
+        }
+        (k - 1..n)
+            .flat_map(|last| {
+                subsets(last, k - 1).into_iter().map(move |mut subset| {
+                    subset.push(last);
+                    subset
+                })
+            })
+            .collect()
+    }
+
+    fn recovers_from_any_needed(needed: usize, total: usize, message: &[u8]) {
+        let code = ErasureCode::new(needed, total);
+        let shards = code.encode(message);
+        assert_eq!(shards.len(), total);
+        for subset in subsets(total, needed) {
+            let received = (0..total)
+                .map(|row| subset.contains(&row).then(|| shards[row].clone()))
+                .collect::<Vec<Option<Vec<u8>>>>();
+            let bytes = code.decode(&received).unwrap();
+            assert_eq!(&bytes[..message.len()], message, "shards {subset:?}");
+            assert!(bytes[message.len()..].iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn recovers_message_from_any_needed_shards() {
+        recovers_from_any_needed(3, 6, b"the quick brown fox jumps over the lazy dog");
+        recovers_from_any_needed(4, 7, &(0..=255).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn recovers_message_with_one_shard_needed() {
+        recovers_from_any_needed(1, 4, b"hello");
+    }
+
+    #[test]
+    fn recovers_message_without_parity() {
+        recovers_from_any_needed(5, 5, b"no parity shards at all");
+    }
+
+    #[test]
+    fn recovers_message_ending_in_zeros() {
+        recovers_from_any_needed(2, 4, &[7, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn needs_enough_shards() {
+        let code = ErasureCode::new(3, 5);
+        let shards = code.encode(b"too few shards");
+        let received = shards
+            .into_iter()
+            .enumerate()
+            .map(|(row, shard)| (row % 2 == 0 && row < 4).then_some(shard))
+            .collect::<Vec<Option<Vec<u8>>>>();
+        assert_eq!(code.decode(&received), None);
+    }
+}
@@ -0,0 +1,25 @@
+use std::{error::Error, fmt::Display};
+
+use crate::client::MAX_SHARDS;
+
+#[derive(Debug)]
+pub enum FragmentError {
+    // The message needs more data fragments than the erasure code can
+    // hold shards, so each would grow past the fragment size
+    TooManyFragments(usize),
+}
+
+impl Error for FragmentError {}
+
+impl Display for FragmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FragmentError::TooManyFragments(needed) => {
+                write!(
+                    f,
+                    "message needs {needed} fragments, more than the {MAX_SHARDS} it can be split into"
+                )
+            }
+        }
+    }
+}
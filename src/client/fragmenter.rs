@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rand::{rngs::StdRng, Rng};
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    client::{ErasureCode, FragmentError, MAX_SHARDS},
+    config::{ErasureCoding, Fragmentation},
+    packet::Fragment,
+};
+
+const DEFAULT_FRAGMENT_BYTES: usize = 600;
+const DEFAULT_REASSEMBLY_TIMEOUT_MILLIS: u64 = 60000;
+
+// Fragments received so far of a message being put back together
+struct PartialMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    needed: usize,
+    length: usize,
+    deadline: Instant,
+}
+
+// A message given up on before enough of its fragments arrived
+pub struct Abandoned {
+    pub id: u64,
+    pub received: usize,
+    pub needed: usize,
+    pub count: usize,
+}
+
+// Splits messages too large for a single packet into fixed-size
+// fragments, and puts the fragments a client receives back together;
+// with erasure coding every message is split, and parity fragments
+// are added so that any `needed` of every `fragments` recover it
+pub struct Fragmenter {
+    fragment_bytes: usize,
+    coding: Option<ErasureCoding>,
+    timeout: Duration,
+    partial: HashMap<u64, PartialMessage>,
+    // Messages already put back together or given up on, so that late
+    // fragments of theirs are not mistaken for the start of another
+    // one, until the reassembly timeout has passed once more
+    finished: HashMap<u64, Instant>,
+    rng: StdRng,
+}
+
+impl Fragmenter {
+    pub fn new(fragmentation: &Fragmentation, coding: Option<ErasureCoding>, rng: StdRng) -> Self {
+        Self {
+            coding,
+            fragment_bytes: fragmentation
+                .fragment_bytes
+                .unwrap_or(DEFAULT_FRAGMENT_BYTES)
+                .max(1),
+            timeout: Duration::from_millis(
+                fragmentation
+                    .timeout_millis
+                    .unwrap_or(DEFAULT_REASSEMBLY_TIMEOUT_MILLIS),
+            ),
+            partial: HashMap::new(),
+            finished: HashMap::new(),
+            rng,
+        }
+    }
+
+    pub fn erasure_coded(&self) -> bool {
+        self.coding.is_some()
+    }
+
+    // Splits a serialised message into fragments, returning them along
+    // with the id they are sent under; a message needing more data
+    // fragments than the code asks for keeps its ratio of parity, and
+    // one needing more than the code can hold is refused
+    pub fn split(&mut self, bytes: &[u8]) -> Result<(u64, Vec<Fragment>), FragmentError> {
+        let data_fragments = bytes.len().div_ceil(self.fragment_bytes).max(1);
+        if data_fragments > MAX_SHARDS {
+            return Err(FragmentError::TooManyFragments(data_fragments));
+        }
+        let id = self.rng.random();
+        let (needed, count) = match &self.coding {
+            Some(coding) => {
+                let needed = coding.needed.max(1).max(data_fragments);
+                let count =
+                    (coding.fragments.max(coding.needed) * needed).div_ceil(coding.needed.max(1));
+                (needed, count)
+            }
+            None => (data_fragments, data_fragments),
+        };
+        let code = ErasureCode::new(needed, count.min(MAX_SHARDS));
+        let shards = code.encode(bytes);
+        let count = shards.len();
+        let needed = needed.min(count);
+        let fragments = shards
+            .into_iter()
+            .enumerate()
+            .map(|(index, shard)| Fragment {
+                index,
+                count,
+                needed,
+                length: bytes.len(),
+                data: BASE64.encode(shard),
+            })
+            .collect();
+        Ok((id, fragments))
+    }
+
+    // Adds a received fragment, returning the serialised message once
+    // enough of its fragments have arrived
+    pub fn add(&mut self, id: u64, fragment: Fragment, now: Instant) -> Option<Vec<u8>> {
+        if self.finished.contains_key(&id)
+            || fragment.index >= fragment.count
+            || fragment.needed > fragment.count
+        {
+            return None;
+        }
+        let data = BASE64.decode(&fragment.data).ok()?;
+        let partial = self.partial.entry(id).or_insert_with(|| PartialMessage {
+            fragments: vec![None; fragment.count],
+            received: 0,
+            needed: fragment.needed,
+            length: fragment.length,
+            deadline: now + self.timeout,
+        });
+        let slot = partial.fragments.get_mut(fragment.index)?;
+        if slot.is_none() {
+            *slot = Some(data);
+            partial.received += 1;
+        }
+        if partial.received < partial.needed {
+            return None;
+        }
+        let partial = self.partial.remove(&id)?;
+        self.finished.insert(id, now + self.timeout);
+        let mut bytes =
+            ErasureCode::new(partial.needed, partial.fragments.len()).decode(&partial.fragments)?;
+        if bytes.len() < partial.length {
+            return None;
+        }
+        bytes.truncate(partial.length);
+        Some(bytes)
+    }
+
+    // When the next message being put back together times out, or the
+    // next finished one is forgotten, if any
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.partial
+            .values()
+            .map(|partial| partial.deadline)
+            .chain(self.finished.values().copied())
+            .min()
+    }
+
+    // Gives up on every message whose missing fragments are overdue,
+    // and forgets finished messages no more fragments are expected of
+    pub fn take_expired(&mut self, now: Instant) -> Vec<Abandoned> {
+        self.finished.retain(|_, forget_at| *forget_at > now);
+        let expired = self
+            .partial
+            .iter()
+            .filter(|(_, partial)| partial.deadline <= now)
+            .map(|(&id, _)| id)
+            .collect::<Vec<u64>>();
+        expired
+            .into_iter()
+            .filter_map(|id| {
+                self.finished.insert(id, now + self.timeout);
+                self.partial.remove(&id).map(|partial| (id, partial))
+            })
+            .map(|(id, partial)| Abandoned {
+                id,
+                received: partial.received,
+                needed: partial.needed,
+                count: partial.fragments.len(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn fragmenter(coding: Option<ErasureCoding>) -> Fragmenter {
+        Fragmenter::new(
+            &Fragmentation {
+                fragment_bytes: Some(4),
+                timeout_millis: Some(1000),
+            },
+            coding,
+            StdRng::seed_from_u64(0),
+        )
+    }
+
+    #[test]
+    fn keeps_trailing_zeros_of_message() {
+        let message = [1, 2, 3, 0, 0, 0, 0, 0, 0];
+        let mut fragmenter = fragmenter(Some(ErasureCoding {
+            needed: 2,
+            fragments: 4,
+        }));
+        let (id, fragments) = fragmenter.split(&message).unwrap();
+        let now = Instant::now();
+        let bytes = fragments
+            .into_iter()
+            .skip(1)
+            .find_map(|fragment| fragmenter.add(id, fragment, now));
+        assert_eq!(bytes.as_deref(), Some(&message[..]));
+    }
+
+    #[test]
+    fn forgets_abandoned_messages_after_timeout() {
+        let mut fragmenter = fragmenter(None);
+        let (id, mut fragments) = fragmenter.split(b"abcdefgh").unwrap();
+        let late = fragments.pop().unwrap();
+        let now = Instant::now();
+        let timeout = Duration::from_millis(1000);
+        assert_eq!(fragmenter.add(id, fragments.remove(0), now), None);
+        assert_eq!(fragmenter.take_expired(now + timeout).len(), 1);
+        // Late fragments are ignored until the timeout passes once more
+        assert_eq!(fragmenter.add(id, late, now + timeout), None);
+        assert!(fragmenter.partial.is_empty());
+        assert_eq!(fragmenter.next_deadline(), Some(now + timeout * 2));
+        assert!(fragmenter.take_expired(now + timeout * 2).is_empty());
+        assert!(fragmenter.finished.is_empty());
+        assert_eq!(fragmenter.next_deadline(), None);
+    }
+
+    #[test]
+    fn refuses_message_needing_more_fragments_than_code_holds() {
+        let mut fragmenter = fragmenter(Some(ErasureCoding {
+            needed: 2,
+            fragments: 4,
+        }));
+        let message = vec![1; 4 * MAX_SHARDS];
+        assert_eq!(fragmenter.split(&message).unwrap().1.len(), MAX_SHARDS);
+        assert!(matches!(
+            fragmenter.split(&[message, vec![1]].concat()),
+            Err(FragmentError::TooManyFragments(257))
+        ));
+    }
+}
@@ -0,0 +1,9 @@
+use std::time::Duration;
+
+// A client's mailbox at a provider, which the client fetches from
+// every `fetch_interval` rather than receiving packets as they arrive
+#[derive(Clone, Debug)]
+pub struct Mailbox {
+    pub provider: String,
+    pub fetch_interval: Duration,
+}
@@ -1,7 +1,27 @@
+mod ack_tracker;
 mod client;
 mod client_command;
 mod client_send_error;
+mod cover_traffic;
+mod delivery_report;
+mod erasure_code;
+mod fragment_error;
+mod fragmenter;
+mod mailbox;
+mod received_message;
+mod reply_blocks;
+mod send_queue;
 
+pub use ack_tracker::{AckTracker, PendingMessage};
 pub use client::Client;
 pub use client_command::ClientCommand;
 pub use client_send_error::ClientSendError;
+pub use cover_traffic::CoverTraffic;
+pub use delivery_report::{DeliveryReport, DeliveryStatus};
+pub use erasure_code::{ErasureCode, MAX_SHARDS};
+pub use fragment_error::FragmentError;
+pub use fragmenter::Fragmenter;
+pub use mailbox::Mailbox;
+pub use received_message::ReceivedMessage;
+pub use reply_blocks::ReplyBlocks;
+pub use send_queue::{QueuedMessage, SendQueue};
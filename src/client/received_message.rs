@@ -0,0 +1,11 @@
+// A message handed by a client to its user
+#[derive(Clone, Debug)]
+pub struct ReceivedMessage {
+    // The sender, if the message names one; for replies, whom the
+    // answered message was sent to
+    pub from: Option<String>,
+    pub body: String,
+    // Handle of the SURB the message came with, if any, to reply
+    // through
+    pub surb: Option<u64>,
+}
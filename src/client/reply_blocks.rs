@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use rand::{rngs::StdRng, Rng};
+use sphinx_packet::SURB;
+
+use crate::config::Surbs;
+
+// Keeps the SURB state of a client: which of its messages are waiting
+// for a reply, and the SURBs it has received to reply through
+pub struct ReplyBlocks {
+    anonymous: bool,
+    // Id each reply comes back under, mapped to whom the answered
+    // message was sent to
+    awaiting: HashMap<[u8; 16], String>,
+    received: HashMap<u64, SURB>,
+    next_handle: u64,
+    rng: StdRng,
+}
+
+impl ReplyBlocks {
+    pub fn new(surbs: &Surbs, rng: StdRng) -> Self {
+        Self {
+            anonymous: surbs.anonymous.unwrap_or_default(),
+            awaiting: HashMap::new(),
+            received: HashMap::new(),
+            next_handle: 0,
+            rng,
+        }
+    }
+
+    // Whether messages leave out their sender
+    pub fn anonymous(&self) -> bool {
+        self.anonymous
+    }
+
+    // Draws the id that replies to a message sent to `to` come back
+    // under
+    pub fn await_reply(&mut self, to: &str) -> [u8; 16] {
+        let id = self.rng.random();
+        self.awaiting.insert(id, to.to_owned());
+        id
+    }
+
+    // Whom the message answered by the reply with the given id was
+    // sent to; every message is answered at most once, since each SURB
+    // can only be used once
+    pub fn take_awaiting(&mut self, id: &[u8; 16]) -> Option<String> {
+        self.awaiting.remove(id)
+    }
+
+    // Keeps a received SURB until the user replies through it,
+    // returning the handle to reply with
+    pub fn store(&mut self, surb: SURB) -> u64 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.received.insert(handle, surb);
+        handle
+    }
+
+    pub fn take(&mut self, handle: u64) -> Option<SURB> {
+        self.received.remove(&handle)
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Exp};
+use tokio::time::{Duration, Instant};
+
+use crate::packet::Packet;
+
+// A message waiting for a sending slot
+pub struct QueuedMessage {
+    pub id: u64,
+    pub to: String,
+    pub body: String,
+    pub path_length: Option<usize>,
+    // Time since the start of the run at which the user sent it
+    pub sent_at_micros: u64,
+    pub queued_at: Instant,
+}
+
+// Messages a client in constant-rate mode holds until its next
+// sending slot, the slots coming up as a Poisson process; a slot with
+// no message waiting is filled with cover instead, so that the
+// client's sending pattern gives nothing away
+pub struct SendQueue {
+    rate: Exp<f64>,
+    next_slot: Instant,
+    messages: VecDeque<QueuedMessage>,
+    // Remaining fragments of the last message sent, which take the
+    // slots that follow before any other message
+    fragments: VecDeque<Packet>,
+    rng: StdRng,
+}
+
+impl SendQueue {
+    // Returns no queue unless the rate is positive
+    pub fn new(rate_per_second: f64, mut rng: StdRng) -> Option<Self> {
+        if rate_per_second <= 0.0 {
+            return None;
+        }
+        let rate = Exp::new(rate_per_second).ok()?;
+        let next_slot = Instant::now() + Duration::from_secs_f64(rate.sample(&mut rng));
+        Some(Self {
+            rate,
+            next_slot,
+            messages: VecDeque::new(),
+            fragments: VecDeque::new(),
+            rng,
+        })
+    }
+
+    pub fn push(&mut self, message: QueuedMessage) {
+        self.messages.push_back(message);
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn next_slot(&self) -> Instant {
+        self.next_slot
+    }
+
+    // Uses up the current slot, returning the message to send in it if
+    // one is waiting
+    pub fn take_slot(&mut self, now: Instant) -> Option<QueuedMessage> {
+        self.next_slot = now + Duration::from_secs_f64(self.rate.sample(&mut self.rng));
+        self.messages.pop_front()
+    }
+
+    // Uses up the current slot for the next remaining fragment, if
+    // there is one
+    pub fn take_fragment(&mut self, now: Instant) -> Option<Packet> {
+        let fragment = self.fragments.pop_front()?;
+        self.next_slot = now + Duration::from_secs_f64(self.rate.sample(&mut self.rng));
+        Some(fragment)
+    }
+
+    // Holds the remaining fragments of a message for the slots to come
+    pub fn defer(&mut self, fragments: impl IntoIterator<Item = Packet>) {
+        self.fragments.extend(fragments);
+    }
+
+    // Puts a message that could not be sent in its slot back at the
+    // front of the queue
+    pub fn requeue(&mut self, message: QueuedMessage) {
+        self.messages.push_front(message);
+    }
+}
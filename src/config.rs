@@ -1,4 +1,4 @@
-use std::{error::Error, fmt::Display};
+use std::{collections::HashMap, error::Error, fmt::Display};
 
 use config::{Config as ExternalConfig, ConfigError as ExternalConfigError};
 use serde::{Deserialize, Serialize};
@@ -9,26 +9,458 @@ pub struct Directory {
 }
 
 #[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Recipients {
+    // Always pick from these ids
+    Fixed(Vec<String>),
+    // Pick this many random contacts once, then pick from those
+    Random(usize),
+    // Pick from every other client
+    All,
+    // Pick from the client's contacts in the social graph
+    Contacts,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct MessageSize {
+    pub min_bytes: usize,
+    pub max_bytes: usize,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Schedule {
+    // Send exactly once per interval
+    Fixed,
+    // Poisson process whose mean inter-send time is the interval
+    Exponential,
+    // Alternate between on periods, during which sends are Poisson,
+    // and silent off periods; both period lengths are Pareto
+    // distributed with the given shape and scales
+    ParetoOnOff {
+        shape: f64,
+        on_scale_millis: u64,
+        off_scale_millis: u64,
+    },
+    // Poisson process whose rate follows a cosine over the period,
+    // peaking at `peak_millis` at `1 + amplitude` times the base rate
+    Diurnal {
+        period_millis: Option<u64>,
+        peak_millis: Option<u64>,
+        amplitude: f64,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Traffic {
+    pub recipients: Option<Recipients>,
+    pub schedule: Option<Schedule>,
+    pub interval_millis: Option<u64>,
+    pub rate_per_second: Option<f64>,
+    pub message_size: Option<MessageSize>,
+    pub start_millis: Option<u64>,
+    pub stop_millis: Option<u64>,
+    // Overrides the network's path length for this traffic's messages
+    pub path_length: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphModel {
+    // Every pair of clients is connected with probability `p`
+    ErdosRenyi { p: f64 },
+    // Preferential attachment, each client joining with `m` edges
+    BarabasiAlbert { m: usize },
+    // Each client picks `contacts` contacts, favouring globally popular
+    // clients according to a Zipf law with the given exponent
+    Zipf { contacts: usize, exponent: f64 },
+    // Whitespace or comma separated pairs of ids, one edge per line
+    EdgeList { path: String },
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SocialGraph {
+    pub model: GraphModel,
+    // Zipf exponent used when picking among a client's contacts, with
+    // better connected contacts being picked more often; zero picks
+    // uniformly
+    pub popularity_skew: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceFormat {
+    Jsonl,
+    Csv,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Trace {
+    pub path: String,
+    // Inferred from the file extension if unset
+    pub format: Option<TraceFormat>,
+    // How many times faster than real time the trace is replayed
+    pub speed: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct Client {
     pub id: String,
     pub buffer_size: Option<usize>,
+    pub overflow: Option<OverflowPolicy>,
+    pub traffic: Option<Traffic>,
+    pub traffic_profile: Option<String>,
+    // Provider holding the client's mailbox; clients are spread over
+    // the providers in turn if unset
+    pub provider: Option<String>,
+    // Overrides how often the client fetches its mailbox
+    pub fetch_interval_millis: Option<u64>,
+    // Overrides the cover traffic the client sends
+    pub cover: Option<Cover>,
+    // Overrides the network's availability for the client
+    pub availability: Option<Availability>,
+    // Overrides the erasure coding of the client's messages
+    pub erasure_coding: Option<ErasureCoding>,
+}
+
+// Loopix-style cover traffic, which a client sends as Poisson
+// processes at the given rates whatever its user does
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Cover {
+    // Cover routed back to the client itself
+    pub loop_rate_per_second: Option<f64>,
+    // Cover sent to a random client, which discards it
+    pub drop_rate_per_second: Option<f64>,
+    // Puts the client in constant-rate mode, where it sends a packet in
+    // every slot of a Poisson process at this rate: the user's next
+    // queued message if there is one, and drop cover otherwise
+    pub send_rate_per_second: Option<f64>,
+}
+
+// End-to-end acknowledgements: recipients acknowledge every message,
+// and senders retransmit a message over a fresh route when its ACK
+// does not arrive in time
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Acks {
+    pub timeout_millis: Option<u64>,
+    // Times a message is sent at most, the first time included
+    pub max_attempts: Option<u32>,
+}
+
+// Splitting of messages too large for a single Sphinx packet
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Fragmentation {
+    // Bytes of the serialised message carried by each fragment
+    pub fragment_bytes: Option<usize>,
+    // How long a recipient waits for the missing fragments of a
+    // message after receiving its first one
+    pub timeout_millis: Option<u64>,
+}
+
+// k-of-n erasure coding: every message is sent as `fragments`
+// fragments over disjoint routes, any `needed` of which recover it
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ErasureCoding {
+    pub needed: usize,
+    pub fragments: usize,
+}
+
+// An active attacker on the network links, which captures packets and
+// sends copies of them on to the same node again later
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Adversary {
+    // Chance that a packet is replayed
+    pub replay_probability: Option<f64>,
+    pub replay_delay_millis: Option<u64>,
+}
+
+// Single-use reply blocks: every message carries a SURB leading back
+// to its sender, which the recipient can answer through. A SURB
+// carries a single packet, so a reply is never fragmented and is
+// refused unless its serialised message fits in one Sphinx payload,
+// of 1007 bytes, which leaves roughly 950 bytes for its body
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Surbs {
+    // Leaves the sender out of messages, so that recipients can only
+    // answer through the SURB; such messages are not acknowledged, as
+    // the recipient does not know whom to acknowledge them to
+    pub anonymous: Option<bool>,
+    // Has users answer every message that comes with a SURB
+    pub auto_reply: Option<bool>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Server {
     pub buffer_size: Option<usize>,
+    pub overflow: Option<OverflowPolicy>,
+}
+
+// What a node's full queue does with one more packet; other commands,
+// such as registrations and mailbox fetches, are never dropped and
+// wait for room instead. The server never waits for room in a node's
+// queue, so that the two never wait on each other: whatever it cannot
+// fit is dropped
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    // Waits for room
+    Block,
+    // Drops the packet being sent
+    #[default]
+    DropNewest,
+    // Drops the packet that has been waiting longest
+    DropOldest,
+    // Drops a packet picked uniformly from those waiting and the one
+    // being sent
+    DropRandom,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    // Every hop is picked from all mix nodes, or from the other clients
+    // if there are no mix nodes
+    #[default]
+    FreeRoute,
+    // One mix node from every layer, in layer order
+    Stratified,
+    // The same position in every layer, so that the mix nodes form
+    // fixed chains which each route follows end to end
+    Cascade,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteSelection {
+    // Every candidate node is equally likely
+    #[default]
+    Uniform,
+    // Nodes are picked proportionally to their advertised capacity
+    CapacityWeighted,
+    // Nodes are picked inversely proportionally to their advertised
+    // latency
+    LatencyAware,
+    // Nodes used by any of the client's last `window` routes are only
+    // picked when there are too few other nodes
+    AvoidRecent {
+        window: usize,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Mixing {
+    // Hold every packet for the delay its sender picked
+    #[default]
+    Continuous,
+    // Hold packets until `threshold` have arrived, then send them all
+    Threshold {
+        threshold: usize,
+    },
+    // Send every held packet once per interval
+    Timed {
+        interval_millis: u64,
+    },
+    // Send every held packet once `threshold` have arrived or the
+    // interval since the last flush has passed, whichever comes first
+    ThresholdOrTimed {
+        threshold: usize,
+        interval_millis: u64,
+    },
+    // Once per interval, send `min(n - min_pool, ceil(n * fraction))` of the
+    // `n` held packets, picked at random
+    CottrellPool {
+        interval_millis: u64,
+        min_pool: usize,
+        fraction: f64,
+    },
+    // As the Cottrell pool, except that every held packet is sent
+    // independently with the probability that makes the expected
+    // number sent the same, though never so many that fewer than
+    // `min_pool` stay behind
+    BinomialPool {
+        interval_millis: u64,
+        min_pool: usize,
+        fraction: f64,
+    },
+}
+
+// Distribution every per-hop Sphinx delay is drawn from, around the
+// mean delay that the hop advertises in the directory
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DelayDistribution {
+    #[default]
+    Exponential,
+    // Always exactly the mean
+    Fixed,
+    // Uniform between zero and twice the mean
+    Uniform,
+    // No delay at all, whatever the mean
+    Zero,
+}
+
+// How a node goes offline and comes back
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ChurnModel {
+    // Time online until the node fails and time offline until it is
+    // repaired are exponentially distributed around the given means
+    Random { mtbf_millis: u64, mttr_millis: u64 },
+    // Offline during each of the given windows, measured from the
+    // start of the run
+    Schedule(Vec<OfflineWindow>),
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct OfflineWindow {
+    pub start_millis: u64,
+    pub end_millis: u64,
+}
+
+// Availability of a node, which drops every packet while offline;
+// replaces the network's per-packet drop probability for the nodes it
+// applies to
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Availability {
+    pub model: ChurnModel,
+    // Whether the node leaves the directory while offline, registering
+    // again once it is back
+    pub deregister: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Network {
+    pub topology: Option<Topology>,
+    pub route_selection: Option<RouteSelection>,
+    // Number of intermediate hops between sender and recipient on a
+    // free route; stratified and cascade routes take one per layer
+    pub path_length: Option<usize>,
+    // Mean per-hop Sphinx delay every node advertises, unless
+    // overridden for mix nodes
+    pub average_delay_millis: Option<u64>,
+    pub delay_distribution: Option<DelayDistribution>,
+    // Chance that a forwarding node drops a packet
+    pub drop_probability: Option<f64>,
+    // Availability of every forwarding node, unless overridden for a
+    // mix node or client
+    pub availability: Option<Availability>,
+    // How every forwarding node mixes, unless overridden for a mix node
+    pub mixing: Option<Mixing>,
+    // How often every node moves on to a fresh Sphinx key, clearing the
+    // replay tags kept for the key before the one it replaces; keys are
+    // never rotated if unset
+    pub key_rotation_millis: Option<u64>,
+}
+
+// Provider nodes, named "provider-<index>", which hold packets for
+// their clients until the clients next come online and fetch them
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Providers {
+    pub count: usize,
+    pub buffer_size: Option<usize>,
+    pub overflow: Option<OverflowPolicy>,
+    pub fetch_interval_millis: Option<u64>,
+}
+
+// Overrides for a single mix node
+#[derive(Deserialize, Serialize, Clone)]
+pub struct MixNode {
+    pub id: String,
+    pub capacity: Option<f64>,
+    pub latency_millis: Option<u64>,
+    pub average_delay_millis: Option<u64>,
+    pub mixing: Option<Mixing>,
+    pub availability: Option<Availability>,
+}
+
+// Loop cover that mix nodes send through the network back to
+// themselves; loops that fail to come back expose active attacks such
+// as (n-1) and blending attacks
+#[derive(Deserialize, Serialize, Clone)]
+pub struct MixLoops {
+    pub rate_per_second: f64,
+    // How long a loop may take to come back before it counts as lost
+    pub timeout_millis: Option<u64>,
+    // Number of most recently settled loops the loss is measured over
+    pub window: Option<usize>,
+    // Loss above which a node raises an alert
+    pub alert_threshold: Option<f64>,
+}
+
+// Dedicated mix nodes, which are named "mix-<layer>-<index>"; since
+// Sphinx headers fit at most five hops including the recipient, at
+// most four layers can be routed through
+#[derive(Deserialize, Serialize, Clone)]
+pub struct MixNodes {
+    pub layers: usize,
+    pub nodes_per_layer: usize,
+    pub buffer_size: Option<usize>,
+    pub overflow: Option<OverflowPolicy>,
+    // Relative bandwidth advertised in the directory
+    pub capacity: Option<f64>,
+    // Fixed time each node takes to pass a packet on, on top of its
+    // Sphinx delay; advertised in the directory
+    pub latency_millis: Option<u64>,
+    // Mean Sphinx delay each node advertises in the directory
+    pub average_delay_millis: Option<u64>,
+    pub mixing: Option<Mixing>,
+    pub loops: Option<MixLoops>,
+    pub availability: Option<Availability>,
+    pub nodes: Option<Vec<MixNode>>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Metrics {
     pub enable: Option<bool>,
+    // Address to serve metrics at, "0.0.0.0:5050" if unset
+    pub address: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockMode {
+    // Timers wait for real time to pass
+    #[default]
+    Wall,
+    // Time is frozen and jumps straight to the next scheduled timer
+    // whenever every task is idle
+    Simulated,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
+pub struct Clock {
+    pub mode: Option<ClockMode>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Run {
+    pub duration_millis: Option<u64>,
+    pub max_messages: Option<u64>,
+    pub drain_millis: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct Config {
+    pub seed: Option<u64>,
+    pub clock: Option<Clock>,
+    pub run: Option<Run>,
     pub server: Option<Server>,
     pub directory: Option<Directory>,
+    pub network: Option<Network>,
+    pub mix_nodes: Option<MixNodes>,
+    pub providers: Option<Providers>,
     pub clients: Option<Vec<Client>>,
+    // Cover traffic sent by every client
+    pub cover: Option<Cover>,
+    pub acks: Option<Acks>,
+    pub surbs: Option<Surbs>,
+    pub adversary: Option<Adversary>,
+    pub fragmentation: Option<Fragmentation>,
+    pub erasure_coding: Option<ErasureCoding>,
+    pub traffic_profiles: Option<HashMap<String, Traffic>>,
+    pub social_graph: Option<SocialGraph>,
+    pub trace: Option<Trace>,
     pub metrics: Option<Metrics>,
 }
 
@@ -49,10 +481,12 @@ impl Display for ConfigError {
     }
 }
 
-pub fn load_config(path: &str, prefix: &str) -> Result<Config, ExternalConfigError> {
+pub fn load_config(path: &str, prefix: &str) -> Result<Config, ConfigError> {
     let mut config_builder = ExternalConfig::builder();
     let paths = std::fs::read_dir(path)
-        .map_err(|_| ExternalConfigError::Message(format!("Could not read directory at {path}")))?
+        .map_err(|_| ConfigError {
+            parent: ExternalConfigError::Message(format!("Could not read directory at {path}")),
+        })?
         .filter_map(|result| result.ok())
         .map(|de| de.path());
     for path in paths {
@@ -69,6 +503,7 @@ pub fn load_config(path: &str, prefix: &str) -> Result<Config, ExternalConfigErr
     }
     config_builder
         .add_source(config::Environment::with_prefix(prefix).separator("_"))
-        .build()?
-        .try_deserialize()
+        .build()
+        .and_then(|config| config.try_deserialize())
+        .map_err(|parent| ConfigError { parent })
 }
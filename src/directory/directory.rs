@@ -56,13 +56,7 @@ impl Directory {
                 DirectoryCommand::GetRegistration(id, response_tx) => {
                     match self.registrations.get(&id) {
                         Some(registration) => {
-                            if let Err(e) = response_tx
-                                .send(Ok(DirectoryRegistration {
-                                    id: registration.id.clone(),
-                                    pk: registration.pk,
-                                }))
-                                .await
-                            {
+                            if let Err(e) = response_tx.send(Ok(registration.clone())).await {
                                 eprintln!(
                                                 "[DIRECTORY] Failed to return registration with id \"{id}\": {e}"
                                             );
@@ -83,6 +77,17 @@ impl Directory {
                         eprintln!("[DIRECTORY] Failed to send all registrations: {e}");
                     }
                 }
+                DirectoryCommand::Deregister(id) => {
+                    if self.registrations.remove(&id).is_some() {
+                        println!("[DIRECTORY] Registration with id \"{id}\" has been removed");
+                    }
+                }
+                DirectoryCommand::Rekey(id, pk) => {
+                    if let Some(registration) = self.registrations.get_mut(&id) {
+                        registration.pk = pk;
+                        println!("[DIRECTORY] Registration with id \"{id}\" has a new key");
+                    }
+                }
             }
         }
     }
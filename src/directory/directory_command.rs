@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use tokio::sync::mpsc::Sender as MpscSender;
+use x25519_dalek::PublicKey;
 
 use crate::directory::{
     DirectoryRegistration, DirectoryRegistrationError, GetDirectoryRegistrationError,
@@ -16,4 +17,9 @@ pub enum DirectoryCommand {
         MpscSender<Result<DirectoryRegistration, GetDirectoryRegistrationError>>,
     ),
     GetAllRegistrations(MpscSender<HashMap<String, DirectoryRegistration>>),
+    // Removes the registration with the given id, if there is one
+    Deregister(String),
+    // Replaces the public key of the registration with the given id
+    // after the node has rotated its keys
+    Rekey(String, PublicKey),
 }
@@ -1,7 +1,22 @@
+use std::time::Duration;
+
 use x25519_dalek::PublicKey;
 
+use crate::directory::NodeRole;
+
 #[derive(Clone, Debug)]
 pub struct DirectoryRegistration {
     pub id: String,
     pub pk: PublicKey,
+    pub role: NodeRole,
+    // Relative bandwidth, for capacity-weighted route selection
+    pub capacity: f64,
+    // Time the node takes to pass a packet on, on top of its Sphinx
+    // delay
+    pub latency: Duration,
+    // Mean Sphinx delay senders should pick for the node
+    pub average_delay: Duration,
+    // Provider holding the client's mailbox, which the last hop of
+    // every route to the client goes through
+    pub provider: Option<String>,
 }
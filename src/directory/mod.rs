@@ -3,9 +3,11 @@ mod directory_command;
 mod directory_registration;
 mod directory_registration_error;
 mod get_directory_registration_error;
+mod node_role;
 
 pub use directory::Directory;
 pub use directory_command::DirectoryCommand;
 pub use directory_registration::DirectoryRegistration;
 pub use directory_registration_error::DirectoryRegistrationError;
 pub use get_directory_registration_error::GetDirectoryRegistrationError;
+pub use node_role::NodeRole;
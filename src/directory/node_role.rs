@@ -0,0 +1,9 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeRole {
+    Client,
+    // A dedicated mix node in the given layer, counting from the one
+    // closest to senders
+    Mix { layer: usize },
+    // Stores packets for the clients it serves until they fetch them
+    Provider,
+}
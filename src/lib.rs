@@ -0,0 +1,25 @@
+#![allow(clippy::module_inception)]
+
+mod bytes;
+mod churn;
+mod client;
+pub mod config;
+mod directory;
+mod mix;
+mod packet;
+mod prometheus;
+mod provider;
+mod queue;
+mod registration;
+mod rng;
+mod routing;
+mod server;
+mod simulation;
+mod stats;
+mod sweep;
+mod traffic;
+mod user;
+
+pub use simulation::{Simulation, SimulationBuilder, SimulationError};
+pub use stats::Summary;
+pub use sweep::{Estimate, Sweep, SweepError, SweepPoint, SweepSpec};
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, seq::SliceRandom};
+use tokio::time::Instant;
+
+use crate::{mix::MixStrategy, packet::Packet};
+
+// Threshold, timed, and threshold-or-timed mixes: packets are held
+// until `threshold` have arrived or `interval` has passed since the
+// last flush, then the whole batch is sent in random order; senders'
+// delays are ignored
+pub struct BatchMix {
+    threshold: Option<usize>,
+    interval: Option<Duration>,
+    batch: Vec<Packet>,
+    next_flush: Option<Instant>,
+}
+
+impl BatchMix {
+    pub fn new(threshold: Option<usize>, interval: Option<Duration>) -> Self {
+        let interval = interval.filter(|interval| !interval.is_zero());
+        Self {
+            threshold: threshold.map(|threshold| threshold.max(1)),
+            interval,
+            batch: vec![],
+            next_flush: interval.map(|interval| Instant::now() + interval),
+        }
+    }
+
+    fn release(&mut self, rng: &mut StdRng) -> Vec<Packet> {
+        if let Some(interval) = self.interval {
+            self.next_flush = Some(Instant::now() + interval);
+        }
+        let mut batch = std::mem::take(&mut self.batch);
+        batch.shuffle(rng);
+        batch
+    }
+}
+
+impl MixStrategy for BatchMix {
+    fn admit(
+        &mut self,
+        packet: Packet,
+        _delay: Duration,
+        rng: &mut StdRng,
+    ) -> Vec<(Duration, Packet)> {
+        self.batch.push(packet);
+        if self
+            .threshold
+            .is_some_and(|threshold| self.batch.len() >= threshold)
+        {
+            self.release(rng)
+                .into_iter()
+                .map(|packet| (Duration::ZERO, packet))
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
+    fn next_flush(&self) -> Option<Instant> {
+        self.next_flush
+    }
+
+    fn flush(&mut self, rng: &mut StdRng) -> Vec<Packet> {
+        self.release(rng)
+    }
+}
@@ -0,0 +1,20 @@
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+
+use crate::{mix::MixStrategy, packet::Packet};
+
+// Stop-and-go mixing as in Loopix: every packet is held for the delay
+// its sender picked, independently of any other packet
+pub struct ContinuousMix;
+
+impl MixStrategy for ContinuousMix {
+    fn admit(
+        &mut self,
+        packet: Packet,
+        delay: Duration,
+        _rng: &mut StdRng,
+    ) -> Vec<(Duration, Packet)> {
+        vec![(delay, packet)]
+    }
+}
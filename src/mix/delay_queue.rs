@@ -0,0 +1,76 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+use tokio::time::Instant;
+
+use crate::packet::Packet;
+
+// A packet waiting in a delay queue; ties on the release time are
+// broken by insertion order, so packets due at the same instant leave
+// in the order they arrived
+struct Delayed {
+    release_at: Instant,
+    seq: u64,
+    packet: Packet,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.release_at, self.seq).cmp(&(other.release_at, other.seq))
+    }
+}
+
+// Min-heap of packets keyed by release time, letting a node hold many
+// delayed packets at once and release each one as soon as it is due
+#[derive(Default)]
+pub struct DelayQueue {
+    heap: BinaryHeap<Reverse<Delayed>>,
+    next_seq: u64,
+}
+
+impl DelayQueue {
+    pub fn push(&mut self, release_at: Instant, packet: Packet) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(Reverse(Delayed {
+            release_at,
+            seq,
+            packet,
+        }));
+    }
+
+    // When the earliest held packet is due, if any packet is held
+    pub fn next_release(&self) -> Option<Instant> {
+        self.heap.peek().map(|Reverse(delayed)| delayed.release_at)
+    }
+
+    // Removes and returns every packet due by `now`, earliest first
+    pub fn pop_due(&mut self, now: Instant) -> Vec<Packet> {
+        let mut due = vec![];
+        while self
+            .next_release()
+            .is_some_and(|release_at| release_at <= now)
+        {
+            if let Some(Reverse(delayed)) = self.heap.pop() {
+                due.push(delayed.packet);
+            }
+        }
+        due
+    }
+}
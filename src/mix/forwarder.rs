@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng};
+use sphinx_packet::{header::delays::Delay, route::NodeAddressBytes, SphinxPacket};
+use tokio::time::Instant;
+
+use crate::{
+    bytes::bytes_to_string_truncate_zeroes,
+    config::{Mixing, Network},
+    mix::{new_mix_strategy, DelayQueue, MixStrategy},
+    packet::{Packet, PacketKind},
+    queue::QueueSender,
+    rng::{RngStream, Rngs},
+    server::ServerCommand,
+    stats::Stats,
+};
+
+const DEFAULT_DROP_PROBABILITY: f64 = 0.3;
+
+// Relays processed Sphinx packets on to their next hop, as and when
+// its mix strategy releases them; shared by mix nodes and by clients,
+// which act as mixes on free routes without dedicated mix nodes.
+// Released packets wait out their delay and the link latency in a
+// delay queue rather than blocking the owning actor
+pub struct Forwarder {
+    id: String,
+    log_prefix: String,
+    latency: Duration,
+    drop_probability: f64,
+    online: bool,
+    strategy: Box<dyn MixStrategy>,
+    delayed: DelayQueue,
+    availability_rng: StdRng,
+    mixing_rng: StdRng,
+    stats: Stats,
+}
+
+impl Forwarder {
+    pub fn new(
+        id: &str,
+        log_prefix: &str,
+        latency: Duration,
+        mixing: &Mixing,
+        network: &Network,
+        rngs: &Rngs,
+        stats: Stats,
+    ) -> Self {
+        Self {
+            id: id.to_owned(),
+            log_prefix: log_prefix.to_owned(),
+            latency,
+            drop_probability: network
+                .drop_probability
+                .unwrap_or(DEFAULT_DROP_PROBABILITY)
+                .clamp(0.0, 1.0),
+            online: true,
+            strategy: new_mix_strategy(mixing),
+            delayed: DelayQueue::default(),
+            availability_rng: rngs.stream(RngStream::Availability, id),
+            mixing_rng: rngs.stream(RngStream::Mixing, id),
+            stats,
+        }
+    }
+
+    // Leaves packet drops to the node's availability model instead of
+    // dropping each packet by chance
+    pub fn disable_random_drops(&mut self) {
+        self.drop_probability = 0.0;
+    }
+
+    // Takes the forwarder offline, in which case it drops every packet
+    // instead of forwarding it, or brings it back
+    pub fn set_online(&mut self, online: bool) {
+        self.online = online;
+    }
+
+    pub fn forward(
+        &mut self,
+        packet_id: &str,
+        kind: PacketKind,
+        from: &str,
+        next_hop_packet: SphinxPacket,
+        next_hop_address: NodeAddressBytes,
+        delay: Delay,
+    ) {
+        if self.availability_rng.random_bool(self.drop_probability) {
+            eprintln!(
+                "{}[{}] Node is unavailable at this time",
+                &self.log_prefix, &self.id
+            );
+            self.stats.record_dropped(kind);
+            return;
+        }
+        let to = bytes_to_string_truncate_zeroes(next_hop_address.as_bytes());
+        println!(
+            "{}[{}] Forwarding packet from \"{}\" to \"{}\"",
+            &self.log_prefix, &self.id, from, &to
+        );
+        let packet = Packet::new_with_id(packet_id, &to, &self.id, next_hop_packet).with_kind(kind);
+        let released = self
+            .strategy
+            .admit(packet, delay.to_duration(), &mut self.mixing_rng);
+        let now = Instant::now();
+        for (hold, packet) in released {
+            self.delayed.push(now + hold + self.latency, packet);
+        }
+    }
+
+    // When the forwarder next needs waking up, either for the mix
+    // strategy to flush or for a delayed packet to be sent, if ever
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        match (self.strategy.next_flush(), self.delayed.next_release()) {
+            (Some(flush_at), Some(release_at)) => Some(flush_at.min(release_at)),
+            (flush_at, release_at) => flush_at.or(release_at),
+        }
+    }
+
+    // Flushes the mix strategy if it is due to, then sends on every
+    // delayed packet whose release time has come
+    pub async fn wake_up(&mut self, server_tx: &QueueSender<ServerCommand>) {
+        let now = Instant::now();
+        if self
+            .strategy
+            .next_flush()
+            .is_some_and(|flush_at| flush_at <= now)
+        {
+            self.flush(now);
+        }
+        for packet in self.delayed.pop_due(now) {
+            self.send(packet, server_tx).await;
+        }
+    }
+
+    fn flush(&mut self, now: Instant) {
+        let released = self.strategy.flush(&mut self.mixing_rng);
+        if released.is_empty() {
+            return;
+        }
+        println!(
+            "{}[{}] Flushing {} packets",
+            &self.log_prefix,
+            &self.id,
+            released.len()
+        );
+        for packet in released {
+            self.delayed.push(now + self.latency, packet);
+        }
+    }
+
+    async fn send(&mut self, packet: Packet, server_tx: &QueueSender<ServerCommand>) {
+        let to = packet.to().to_owned();
+        let kind = packet.kind();
+        // Packets held from before the node went offline are lost
+        if !self.online {
+            eprintln!(
+                "{}[{}] Node is offline, dropping packet to \"{to}\"",
+                &self.log_prefix, &self.id
+            );
+            self.stats.record_dropped(kind);
+            return;
+        }
+        if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+            eprintln!(
+                "{}[{}] Unable to forward packet to \"{to}\": {e}",
+                &self.log_prefix, &self.id
+            );
+            self.stats.record_dropped(kind);
+        }
+    }
+}
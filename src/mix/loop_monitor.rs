@@ -0,0 +1,144 @@
+use std::collections::{HashMap, VecDeque};
+
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Exp};
+use tokio::time::{Duration, Instant};
+
+use crate::config::MixLoops;
+
+const DEFAULT_LOOP_TIMEOUT_MILLIS: u64 = 30000;
+const DEFAULT_LOOP_WINDOW: usize = 20;
+const DEFAULT_LOOP_ALERT_THRESHOLD: f64 = 0.5;
+
+// Schedules a mix node's loops as a Poisson process and keeps track of
+// which of them came back in time; a rising share of lost loops means
+// that packets around the node are being held back or dropped, as in
+// an (n-1) or blending attack
+pub struct LoopMonitor {
+    rate: Option<Exp<f64>>,
+    timeout: Duration,
+    window: usize,
+    alert_threshold: f64,
+    next_send: Option<Instant>,
+    next_id: u64,
+    // Deadlines of the loops still out, by loop id
+    pending: HashMap<u64, Instant>,
+    // Whether each of the most recently settled loops was lost
+    outcomes: VecDeque<bool>,
+    alerting: bool,
+    rng: StdRng,
+}
+
+impl LoopMonitor {
+    pub fn new(loops: &MixLoops, rng: StdRng) -> Self {
+        let mut monitor = Self {
+            rate: Some(loops.rate_per_second)
+                .filter(|rate| *rate > 0.0)
+                .and_then(|rate| Exp::new(rate).ok()),
+            timeout: Duration::from_millis(
+                loops.timeout_millis.unwrap_or(DEFAULT_LOOP_TIMEOUT_MILLIS),
+            ),
+            window: loops.window.unwrap_or(DEFAULT_LOOP_WINDOW).max(1),
+            alert_threshold: loops
+                .alert_threshold
+                .unwrap_or(DEFAULT_LOOP_ALERT_THRESHOLD),
+            next_send: None,
+            next_id: 0,
+            pending: HashMap::new(),
+            outcomes: VecDeque::new(),
+            alerting: false,
+            rng,
+        };
+        monitor.next_send = monitor.schedule(Instant::now());
+        monitor
+    }
+
+    // When the monitor next needs waking up, either to send a loop or
+    // to give up on one, if ever
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        let next_deadline = self.pending.values().min().copied();
+        match (self.next_send, next_deadline) {
+            (Some(next_send), Some(next_deadline)) => Some(next_send.min(next_deadline)),
+            (next_send, next_deadline) => next_send.or(next_deadline),
+        }
+    }
+
+    // Whether a loop is due to be sent by `now`, scheduling the next
+    // one if so
+    pub fn take_send_due(&mut self, now: Instant) -> bool {
+        if self.next_send.is_some_and(|next_send| next_send <= now) {
+            self.next_send = self.schedule(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Starts tracking a loop sent at `now`, returning its id
+    pub fn start(&mut self, now: Instant) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(id, now + self.timeout);
+        id
+    }
+
+    // Settles a loop that came back, returning whether it was still
+    // being waited for
+    pub fn settle_returned(&mut self, id: u64) -> bool {
+        if self.pending.remove(&id).is_some() {
+            self.push_outcome(false);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Settles every loop whose deadline has passed as lost, returning
+    // how many were
+    pub fn settle_expired(&mut self, now: Instant) -> usize {
+        let mut expired = self
+            .pending
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<u64>>();
+        expired.sort_unstable();
+        for id in &expired {
+            self.pending.remove(id);
+            self.push_outcome(true);
+        }
+        expired.len()
+    }
+
+    // Share of the loops in the window that were lost
+    pub fn loss(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            0.0
+        } else {
+            self.outcomes.iter().filter(|lost| **lost).count() as f64 / self.outcomes.len() as f64
+        }
+    }
+
+    // Returns the loss when it has just risen above the alert threshold
+    // over a full window; the alert is raised again only after the loss
+    // has dropped back to the threshold or below
+    pub fn check_alert(&mut self) -> Option<f64> {
+        let loss = self.loss();
+        let above = self.outcomes.len() >= self.window && loss > self.alert_threshold;
+        let raised = above && !self.alerting;
+        self.alerting = above;
+        raised.then_some(loss)
+    }
+
+    fn push_outcome(&mut self, lost: bool) {
+        self.outcomes.push_back(lost);
+        while self.outcomes.len() > self.window {
+            self.outcomes.pop_front();
+        }
+    }
+
+    fn schedule(&mut self, now: Instant) -> Option<Instant> {
+        self.rate
+            .map(|rate| now + Duration::from_secs_f64(rate.sample(&mut self.rng)))
+    }
+}
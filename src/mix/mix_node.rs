@@ -0,0 +1,569 @@
+use std::{collections::HashMap, sync::atomic::AtomicU64};
+
+use prometheus_client::metrics::{counter::Counter, family::Family, gauge::Gauge};
+use rand::{rngs::StdRng, Rng};
+use sphinx_packet::ProcessedPacketData;
+use tokio::{
+    sync::mpsc::{self, Sender as MpscSender},
+    time::{sleep_until, Duration, Instant},
+};
+use x25519_dalek::StaticSecret;
+
+use crate::{
+    bytes::bytes_to_string_truncate_zeroes,
+    churn::Churn,
+    config::{Network, Topology},
+    directory::{DirectoryCommand, DirectoryRegistration, NodeRole},
+    mix::{Forwarder, LoopMonitor, MixNodeCommand, MixNodeSettings, NodeKeys, ProcessError},
+    packet::{CoverKind, Message, PacketKind},
+    prometheus::{LoopLabels, LoopStatus, MetricFamilies, NodeLabels, ReplayLabels, ReplayStatus},
+    queue::{self, Overflow, QueueReceiver, QueueSender},
+    registration::{deregister_node, register_in_directory, register_node, rekey_node},
+    rng::{RngStream, Rngs},
+    routing::{mix_layers, new_route_selector, RouteSelector, SphinxBuilder, DEFAULT_PATH_LENGTH},
+    server::{NodeTx, ServerCommand},
+    stats::Stats,
+};
+
+pub struct MixNodeMetrics {
+    loops: Family<LoopLabels, Counter>,
+    loop_loss: Family<NodeLabels, Gauge<f64, AtomicU64>>,
+    loop_alerts: Family<NodeLabels, Counter>,
+    online: Family<NodeLabels, Gauge>,
+    replays: Family<ReplayLabels, Counter>,
+}
+
+// Dedicated mix in one layer of a stratified or cascade network, or
+// one of the pool of mixes on free routes; unlike clients, mix nodes
+// never send or receive messages of their own, only loop cover
+pub struct MixNode {
+    id: String,
+    settings: MixNodeSettings,
+    keys: NodeKeys,
+    directory_tx: MpscSender<DirectoryCommand>,
+    mix_node_tx: QueueSender<MixNodeCommand>,
+    mix_node_rx: QueueReceiver<MixNodeCommand>,
+    metrics: Option<MixNodeMetrics>,
+    forwarder: Forwarder,
+    stats: Stats,
+    loop_monitor: Option<LoopMonitor>,
+    churn: Option<Churn>,
+    address_book: HashMap<String, DirectoryRegistration>,
+    topology: Topology,
+    route_selector: Box<dyn RouteSelector>,
+    path_length: usize,
+    sphinx_builder: SphinxBuilder,
+    routing_rng: StdRng,
+}
+
+impl MixNode {
+    pub fn new(
+        id: &str,
+        settings: MixNodeSettings,
+        directory_tx: MpscSender<DirectoryCommand>,
+        buffer_size: usize,
+        rngs: &Rngs,
+        stats: Stats,
+        network: &Network,
+    ) -> Self {
+        let (mix_node_tx, mix_node_rx) = queue::channel::<MixNodeCommand>(buffer_size);
+        let mut key_rng = rngs.stream(RngStream::Keys, id);
+        let sk = StaticSecret::from(key_rng.random::<[u8; 32]>());
+        let mut forwarder = Forwarder::new(
+            id,
+            "[MIX]",
+            settings.latency,
+            &settings.mixing,
+            network,
+            rngs,
+            stats.clone(),
+        );
+        let churn = settings
+            .availability
+            .as_ref()
+            .map(|availability| Churn::new(availability, rngs.stream(RngStream::Churn, id)));
+        if churn.is_some() {
+            forwarder.disable_random_drops();
+        }
+        Self {
+            id: id.to_owned(),
+            keys: NodeKeys::new(
+                sk,
+                network.key_rotation_millis.map(Duration::from_millis),
+                rngs.stream(RngStream::KeyRotation, id),
+            ),
+            directory_tx,
+            mix_node_tx,
+            mix_node_rx,
+            metrics: None,
+            forwarder,
+            churn,
+            loop_monitor: settings
+                .loops
+                .as_ref()
+                .map(|loops| LoopMonitor::new(loops, rngs.stream(RngStream::Cover, id))),
+            settings,
+            stats,
+            address_book: HashMap::new(),
+            topology: network.topology.unwrap_or_default(),
+            route_selector: new_route_selector(&network.route_selection.unwrap_or_default()),
+            path_length: network.path_length.unwrap_or(DEFAULT_PATH_LENGTH),
+            sphinx_builder: SphinxBuilder::new(
+                id,
+                network.delay_distribution.unwrap_or_default(),
+                key_rng,
+                rngs.stream(RngStream::Delays, id),
+                rngs.stream(RngStream::PacketIds, id),
+            ),
+            routing_rng: rngs.stream(RngStream::Routing, id),
+        }
+    }
+
+    // Has the node drop packets when its queue is full, according to
+    // the overflow policy, rather than have the server drop whichever
+    // packet it cannot fit
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.mix_node_rx.set_overflow(overflow);
+        self
+    }
+
+    // Has the node report its loop cover to Prometheus
+    pub fn with_metrics(mut self, mf: &MetricFamilies) -> Self {
+        self.metrics = Some(MixNodeMetrics {
+            loops: mf.mix_loops.clone(),
+            loop_loss: mf.mix_loop_loss.clone(),
+            loop_alerts: mf.mix_loop_alerts.clone(),
+            online: mf.node_online.clone(),
+            replays: mf.replays.clone(),
+        });
+        self
+    }
+
+    pub async fn listen(&mut self, server_tx: QueueSender<ServerCommand>) {
+        let registration = self.registration();
+        let tx = NodeTx::MixNode(self.mix_node_tx.clone());
+        if !register_node("[MIX]", tx, registration, &server_tx, &self.directory_tx).await {
+            return;
+        }
+        println!(
+            "[MIX][{}] Successfully registered in layer {}",
+            &self.id, self.settings.layer
+        );
+
+        self.record_online();
+
+        println!("[MIX][{}] Starting listening", &self.id);
+        // Loops are routed through other mix nodes, whose keys need
+        // looking up again as they rotate
+        let mut lookup_timer = self
+            .keys
+            .lookup_interval()
+            .filter(|_| self.loop_monitor.is_some());
+        loop {
+            let next_wakeup = self.forwarder.next_wakeup();
+            let next_loop_wakeup = self
+                .loop_monitor
+                .as_ref()
+                .and_then(LoopMonitor::next_wakeup);
+            let next_churn = self.churn.as_ref().and_then(Churn::next_change);
+            let next_rotation = self.keys.next_rotation();
+            let cmd = tokio::select! {
+                cmd = self.mix_node_rx.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => return,
+                },
+                _ = async {
+                    match next_rotation {
+                        Some(next_rotation) => sleep_until(next_rotation).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.rotate_keys().await;
+                    continue;
+                }
+                _ = async {
+                    match &mut lookup_timer {
+                        Some(timer) => timer.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.refresh_address_book().await;
+                    continue;
+                }
+                _ = async {
+                    match next_wakeup {
+                        Some(next_wakeup) => sleep_until(next_wakeup).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.forwarder.wake_up(&server_tx).await;
+                    continue;
+                }
+                _ = async {
+                    match next_loop_wakeup {
+                        Some(next_loop_wakeup) => sleep_until(next_loop_wakeup).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.check_loops(&server_tx).await;
+                    continue;
+                }
+                _ = async {
+                    match next_churn {
+                        Some(next_churn) => sleep_until(next_churn).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.change_availability().await;
+                    continue;
+                }
+            };
+            match cmd {
+                MixNodeCommand::Shutdown => {
+                    return;
+                }
+                MixNodeCommand::ReceivePacket(packet) => {
+                    let kind = packet.kind();
+                    if !self.online() {
+                        eprintln!(
+                            "[MIX][{}] Node is offline, dropping packet from \"{}\"",
+                            &self.id,
+                            packet.from()
+                        );
+                        self.stats.record_dropped(kind);
+                        continue;
+                    }
+                    let (packet_id, _, from, sphinx_packet) = packet.take();
+                    match self.keys.process(sphinx_packet) {
+                        Ok(packet) => match packet.data {
+                            ProcessedPacketData::ForwardHop {
+                                next_hop_packet,
+                                next_hop_address,
+                                delay,
+                            } => {
+                                self.forwarder.forward(
+                                    &packet_id,
+                                    kind,
+                                    &from,
+                                    next_hop_packet,
+                                    next_hop_address,
+                                    delay,
+                                );
+                            }
+                            ProcessedPacketData::FinalHop {
+                                destination,
+                                identifier: _,
+                                payload,
+                            } if bytes_to_string_truncate_zeroes(destination.as_bytes_ref())
+                                == self.id =>
+                            {
+                                let message: Option<Message> = payload
+                                    .recover_plaintext()
+                                    .ok()
+                                    .and_then(|bytes| serde_yaml::from_slice(&bytes).ok());
+                                match message {
+                                    Some(Message {
+                                        kind: PacketKind::Cover(CoverKind::Loop),
+                                        body,
+                                        ..
+                                    }) => self.receive_loop(&body),
+                                    _ => {
+                                        eprintln!(
+                                            "[MIX][{}] Mix nodes only receive their own loops, dropping packet from \"{from}\"",
+                                            &self.id
+                                        );
+                                        self.stats.record_dropped(kind);
+                                    }
+                                }
+                            }
+                            ProcessedPacketData::FinalHop { .. } => {
+                                eprintln!(
+                                    "[MIX][{}] Mix nodes are never the destination of a message, dropping packet from \"{from}\"",
+                                    &self.id
+                                );
+                                self.stats.record_dropped(kind);
+                            }
+                        },
+                        Err(ProcessError::Replayed) => {
+                            eprintln!(
+                                "[MIX][{}] Dropping replayed packet from \"{from}\"",
+                                &self.id
+                            );
+                            self.record_replay();
+                        }
+                        Err(e) => {
+                            eprintln!(
+                                "[MIX][{}] Failed to process Sphinx packet from \"{from}\": {e}",
+                                &self.id
+                            );
+                            self.stats.record_dropped(kind);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get_tx(&self) -> QueueSender<MixNodeCommand> {
+        self.mix_node_tx.clone()
+    }
+
+    // The node's entry in the directory
+    fn registration(&self) -> DirectoryRegistration {
+        DirectoryRegistration {
+            id: self.id.clone(),
+            pk: self.keys.public_key(),
+            role: NodeRole::Mix {
+                layer: self.settings.layer,
+            },
+            capacity: self.settings.capacity,
+            latency: self.settings.latency,
+            average_delay: self.settings.average_delay,
+            provider: None,
+        }
+    }
+
+    // Gives up on loops that are overdue, then sends a loop if one is
+    // due
+    async fn check_loops(&mut self, server_tx: &QueueSender<ServerCommand>) {
+        let now = Instant::now();
+        let Some(monitor) = self.loop_monitor.as_mut() else {
+            return;
+        };
+        let lost = monitor.settle_expired(now);
+        let send_due = monitor.take_send_due(now);
+        if lost > 0 {
+            println!("[MIX][{}] {lost} loops failed to return", &self.id);
+            self.record_loops(LoopStatus::Lost, lost as u64);
+        }
+        if send_due && self.online() {
+            self.send_loop(now, server_tx).await;
+        }
+    }
+
+    fn online(&self) -> bool {
+        self.churn.as_ref().is_none_or(Churn::online)
+    }
+
+    // Moves on to a fresh Sphinx key and publishes it in the directory
+    async fn rotate_keys(&mut self) {
+        let cleared = self.keys.rotate(Instant::now());
+        println!(
+            "[MIX][{}] Rotated keys, clearing {cleared} replay tags",
+            &self.id
+        );
+        rekey_node(
+            "[MIX]",
+            &self.id,
+            self.keys.public_key(),
+            &self.directory_tx,
+        )
+        .await;
+    }
+
+    fn record_replay(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .replays
+                .get_or_create(&ReplayLabels {
+                    node: self.id.clone(),
+                    status: ReplayStatus::Dropped,
+                })
+                .inc();
+        }
+    }
+
+    // Takes the node offline or brings it back, leaving the directory
+    // while offline if the node's availability model says to
+    async fn change_availability(&mut self) {
+        let Some(churn) = self.churn.as_mut() else {
+            return;
+        };
+        let online = churn.change(Instant::now());
+        let deregisters = churn.deregisters();
+        self.forwarder.set_online(online);
+        self.record_online();
+        if online {
+            println!("[MIX][{}] Back online", &self.id);
+            if deregisters {
+                register_in_directory("[MIX]", self.registration(), &self.directory_tx).await;
+            }
+        } else {
+            println!("[MIX][{}] Going offline", &self.id);
+            if deregisters {
+                deregister_node("[MIX]", &self.id, &self.directory_tx).await;
+            }
+        }
+    }
+
+    fn record_online(&self) {
+        if let (Some(metrics), Some(churn)) = (&self.metrics, &self.churn) {
+            metrics
+                .online
+                .get_or_create(&NodeLabels {
+                    node: self.id.clone(),
+                })
+                .set(churn.online() as i64);
+        }
+    }
+
+    // Sends a loop through the other mix nodes back to this one, going
+    // through every other layer in turn on layered topologies
+    async fn send_loop(&mut self, now: Instant, server_tx: &QueueSender<ServerCommand>) {
+        if self.address_book.len() <= 1 && !self.refresh_address_book().await {
+            return;
+        }
+        let route = self.select_loop_route();
+        if route.is_empty() {
+            return;
+        }
+        let Some(loop_id) = self.loop_monitor.as_mut().map(|monitor| monitor.start(now)) else {
+            return;
+        };
+        let message = Message {
+            from: None,
+            body: loop_id.to_string(),
+            sent_at_micros: self.stats.elapsed().as_micros() as u64,
+            kind: PacketKind::Cover(CoverKind::Loop),
+            surb: None,
+            fragment: None,
+        };
+        let registration = self.registration();
+        let packet = match self.sphinx_builder.build(&route, &registration, &message) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!("[MIX][{}] Failed to construct loop packet: {e}", &self.id);
+                return;
+            }
+        };
+        if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+            eprintln!("[MIX][{}] Failed sending loop packet: {e}", &self.id);
+            return;
+        }
+        self.record_loops(LoopStatus::Sent, 1);
+    }
+
+    fn receive_loop(&mut self, body: &str) {
+        let returned = match (self.loop_monitor.as_mut(), body.parse::<u64>()) {
+            (Some(monitor), Ok(loop_id)) => monitor.settle_returned(loop_id),
+            _ => false,
+        };
+        if returned {
+            self.record_loops(LoopStatus::Returned, 1);
+        } else {
+            println!(
+                "[MIX][{}] Ignoring loop that came back after its timeout",
+                &self.id
+            );
+        }
+    }
+
+    // Updates the loop metrics, raising an alert if the loss has just
+    // risen above the threshold
+    fn record_loops(&mut self, status: LoopStatus, count: u64) {
+        let Some(monitor) = self.loop_monitor.as_mut() else {
+            return;
+        };
+        let alert = monitor.check_alert();
+        let loss = monitor.loss();
+        if let Some(loss) = alert {
+            eprintln!(
+                "[MIX][{}] ALERT: loop loss of {:.0}% exceeds the threshold, packets may be being held back or dropped",
+                &self.id,
+                loss * 100.0
+            );
+        }
+        if let Some(metrics) = &self.metrics {
+            let node = NodeLabels {
+                node: self.id.clone(),
+            };
+            metrics
+                .loops
+                .get_or_create(&LoopLabels {
+                    node: self.id.clone(),
+                    status,
+                })
+                .inc_by(count);
+            metrics.loop_loss.get_or_create(&node).set(loss);
+            if alert.is_some() {
+                metrics.loop_alerts.get_or_create(&node).inc();
+            }
+        }
+    }
+
+    // Replaces the address book with every node in the directory,
+    // returning whether the directory could be reached
+    async fn refresh_address_book(&mut self) -> bool {
+        let (response_tx, mut response_rx) =
+            mpsc::channel::<HashMap<String, DirectoryRegistration>>(1);
+        let cmd = DirectoryCommand::GetAllRegistrations(response_tx);
+        if let Err(e) = self.directory_tx.send(cmd).await {
+            eprintln!(
+                "[MIX][{}] Failed to fetch all nodes from directory: {e}",
+                &self.id
+            );
+            return false;
+        }
+        match response_rx.recv().await {
+            Some(address_book) => {
+                self.address_book = address_book;
+                true
+            }
+            None => {
+                eprintln!(
+                    "[MIX][{}] Get all registrations response channel closed before receiving anything",
+                    &self.id
+                );
+                false
+            }
+        }
+    }
+
+    // Picks the hops of a loop; free routes go through other mix nodes
+    // picked by the route selector, layered routes through the layers
+    // after this node's and then those before it, staying on this
+    // node's cascade on cascade topologies
+    fn select_loop_route(&mut self) -> Vec<DirectoryRegistration> {
+        let layers = mix_layers(&self.address_book, self.settings.layers);
+        let own_layer = self.settings.layer;
+        // A layered loop skipping a layer none of whose nodes are known
+        // yet would be shorter than the routes it stands in for
+        if self.topology != Topology::FreeRoute && layers.iter().any(Vec::is_empty) {
+            return vec![];
+        }
+        let route = match self.topology {
+            Topology::FreeRoute => {
+                let candidates = layers
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .filter(|entry| entry.id != self.id)
+                    .collect::<Vec<&DirectoryRegistration>>();
+                let count = self.path_length.min(candidates.len());
+                self.route_selector
+                    .select(&candidates, count, &mut self.routing_rng)
+            }
+            Topology::Stratified => (own_layer + 1..layers.len())
+                .chain(0..own_layer.min(layers.len()))
+                .flat_map(|layer| {
+                    self.route_selector
+                        .select(&layers[layer], 1, &mut self.routing_rng)
+                })
+                .collect(),
+            Topology::Cascade => {
+                let cascade = layers
+                    .get(own_layer)
+                    .and_then(|layer| layer.iter().position(|entry| entry.id == self.id));
+                match cascade {
+                    Some(cascade) => (own_layer + 1..layers.len())
+                        .chain(0..own_layer)
+                        .filter_map(|layer| layers[layer].get(cascade).map(|&entry| entry.clone()))
+                        .collect(),
+                    None => vec![],
+                }
+            }
+        };
+        self.route_selector.record(&route);
+        route
+    }
+}
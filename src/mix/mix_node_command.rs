@@ -0,0 +1,15 @@
+use crate::{packet::Packet, queue::Droppable};
+
+pub enum MixNodeCommand {
+    ReceivePacket(Packet),
+    Shutdown,
+}
+
+impl Droppable for MixNodeCommand {
+    fn packet(&self) -> Option<&Packet> {
+        match self {
+            MixNodeCommand::ReceivePacket(packet) => Some(packet),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use std::time::Duration;
+
+use crate::config::{Availability, MixLoops, Mixing};
+
+// Parameters of a single mix node, resolved from the mix node config
+// and any overrides for that node
+#[derive(Clone)]
+pub struct MixNodeSettings {
+    pub layer: usize,
+    // Number of layers in the network, for routing loops through them
+    pub layers: usize,
+    pub capacity: f64,
+    pub latency: Duration,
+    pub average_delay: Duration,
+    pub mixing: Mixing,
+    pub loops: Option<MixLoops>,
+    pub availability: Option<Availability>,
+}
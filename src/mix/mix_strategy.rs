@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use tokio::time::Instant;
+
+use crate::{
+    config::Mixing,
+    mix::{BatchMix, ContinuousMix, PoolKind, PoolMix},
+    packet::Packet,
+};
+
+// Decides when the packets a node forwards leave it again
+pub trait MixStrategy: Send {
+    // Takes in a packet along with the delay its sender picked for
+    // this hop, returning the packets to send on as a result, each
+    // with how long to hold it before sending
+    fn admit(
+        &mut self,
+        packet: Packet,
+        delay: Duration,
+        rng: &mut StdRng,
+    ) -> Vec<(Duration, Packet)>;
+
+    // When the strategy next wants to flush, if it holds packets back
+    // on a timer
+    fn next_flush(&self) -> Option<Instant> {
+        None
+    }
+
+    // Called once the time returned by `next_flush` has come, returning
+    // the packets to send on now
+    fn flush(&mut self, _rng: &mut StdRng) -> Vec<Packet> {
+        vec![]
+    }
+}
+
+pub fn new_mix_strategy(mixing: &Mixing) -> Box<dyn MixStrategy> {
+    match *mixing {
+        Mixing::Continuous => Box::new(ContinuousMix),
+        Mixing::Threshold { threshold } => Box::new(BatchMix::new(Some(threshold), None)),
+        Mixing::Timed { interval_millis } => Box::new(BatchMix::new(
+            None,
+            Some(Duration::from_millis(interval_millis)),
+        )),
+        Mixing::ThresholdOrTimed {
+            threshold,
+            interval_millis,
+        } => Box::new(BatchMix::new(
+            Some(threshold),
+            Some(Duration::from_millis(interval_millis)),
+        )),
+        Mixing::CottrellPool {
+            interval_millis,
+            min_pool,
+            fraction,
+        } => Box::new(PoolMix::new(
+            PoolKind::Cottrell,
+            Duration::from_millis(interval_millis),
+            min_pool,
+            fraction,
+        )),
+        Mixing::BinomialPool {
+            interval_millis,
+            min_pool,
+            fraction,
+        } => Box::new(PoolMix::new(
+            PoolKind::Binomial,
+            Duration::from_millis(interval_millis),
+            min_pool,
+            fraction,
+        )),
+    }
+}
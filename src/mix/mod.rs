@@ -0,0 +1,25 @@
+mod batch_mix;
+mod continuous_mix;
+mod delay_queue;
+mod forwarder;
+mod loop_monitor;
+mod mix_node;
+mod mix_node_command;
+mod mix_node_settings;
+mod mix_strategy;
+mod node_keys;
+mod pool_mix;
+mod process_error;
+
+pub use batch_mix::BatchMix;
+pub use continuous_mix::ContinuousMix;
+pub use delay_queue::DelayQueue;
+pub use forwarder::Forwarder;
+pub use loop_monitor::LoopMonitor;
+pub use mix_node::MixNode;
+pub use mix_node_command::MixNodeCommand;
+pub use mix_node_settings::MixNodeSettings;
+pub use mix_strategy::{new_mix_strategy, MixStrategy};
+pub use node_keys::NodeKeys;
+pub use pool_mix::{PoolKind, PoolMix};
+pub use process_error::ProcessError;
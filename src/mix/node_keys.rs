@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+
+use rand::{rngs::StdRng, Rng};
+use sphinx_packet::{constants::REPLAY_TAG_SIZE, ProcessedPacket, SphinxPacket};
+use tokio::time::{interval_at, Duration, Instant, Interval, MissedTickBehavior};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::mix::ProcessError;
+
+// A node's Sphinx key for one epoch, along with the replay tags of the
+// headers processed with it
+struct Epoch {
+    sk: StaticSecret,
+    tags: HashSet<[u8; REPLAY_TAG_SIZE]>,
+}
+
+impl Epoch {
+    fn new(sk: StaticSecret) -> Self {
+        Self {
+            sk,
+            tags: HashSet::new(),
+        }
+    }
+}
+
+// The Sphinx keys a node processes packets with; every header processed
+// leaves a replay tag derived from its shared secret, so that a packet
+// seen before is refused. With key rotation the node moves to a fresh
+// key every epoch, still accepting packets built for the previous one
+// until the next rotation, when that key's tags are cleared along with
+// it since no packet can be processed with it any more
+pub struct NodeKeys {
+    current: Epoch,
+    previous: Option<Epoch>,
+    rotation: Option<Duration>,
+    next_rotation: Option<Instant>,
+    rng: StdRng,
+}
+
+impl NodeKeys {
+    pub fn new(sk: StaticSecret, rotation: Option<Duration>, rng: StdRng) -> Self {
+        let rotation = rotation.filter(|rotation| !rotation.is_zero());
+        Self {
+            current: Epoch::new(sk),
+            previous: None,
+            rotation,
+            next_rotation: rotation.map(|rotation| Instant::now() + rotation),
+            rng,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.current.sk)
+    }
+
+    pub fn next_rotation(&self) -> Option<Instant> {
+        self.next_rotation
+    }
+
+    // Ticks a quarter of the way into every epoch, for nodes that route
+    // packets to look up the directory once every node has rotated;
+    // the keys they find stay valid until the end of the following
+    // epoch, leaving the packets built late with them most of an
+    // epoch to arrive
+    pub fn lookup_interval(&self) -> Option<Interval> {
+        let rotation = self.rotation?;
+        let mut interval = interval_at(Instant::now() + rotation / 4, rotation);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Some(interval)
+    }
+
+    // Moves on to a fresh key, returning how many replay tags were
+    // cleared with the key it retires
+    pub fn rotate(&mut self, now: Instant) -> usize {
+        let sk = StaticSecret::from(self.rng.random::<[u8; 32]>());
+        let retired = self
+            .previous
+            .replace(std::mem::replace(&mut self.current, Epoch::new(sk)));
+        self.next_rotation = self.rotation.map(|rotation| now + rotation);
+        retired.map_or(0, |epoch| epoch.tags.len())
+    }
+
+    // Processes a packet with whichever key its header was built for,
+    // refusing it if a header with the same shared secret has been
+    // processed with that key before
+    pub fn process(&mut self, packet: SphinxPacket) -> Result<ProcessedPacket, ProcessError> {
+        let header = &packet.header;
+        let mut secret = header.compute_expanded_shared_secret(&self.current.sk);
+        let mut epoch = &mut self.current;
+        // Packets built for the previous key are still accepted
+        if let (Err(_), Some(previous)) = (
+            header.ensure_header_integrity(&secret),
+            self.previous.as_mut(),
+        ) {
+            let previous_secret = header.compute_expanded_shared_secret(&previous.sk);
+            if header.ensure_header_integrity(&previous_secret).is_ok() {
+                secret = previous_secret;
+                epoch = previous;
+            }
+        }
+        // Headers that fail to verify never leave a tag, so that
+        // garbage cannot fill the cache
+        header.ensure_header_integrity(&secret)?;
+        if !epoch.tags.insert(*secret.replay_tag()) {
+            return Err(ProcessError::Replayed);
+        }
+        Ok(packet.process_with_expanded_secret(&secret)?)
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use tokio::time::Instant;
+
+use crate::{mix::MixStrategy, packet::Packet};
+
+pub enum PoolKind {
+    // Sends a fixed share of the pool, as Mixmaster does
+    Cottrell,
+    // Sends every packet with a fixed chance, as in the binomial mix
+    // of Díaz and Serjantov, but never more than the pool can spare
+    Binomial,
+}
+
+// Timed dynamic pool mixes: once per interval some of the held packets
+// are sent, while at least `min_pool` stay behind so that any packet
+// can linger for several rounds; senders' delays are ignored
+pub struct PoolMix {
+    kind: PoolKind,
+    interval: Duration,
+    min_pool: usize,
+    fraction: f64,
+    pool: Vec<Packet>,
+    next_flush: Instant,
+}
+
+impl PoolMix {
+    pub fn new(kind: PoolKind, interval: Duration, min_pool: usize, fraction: f64) -> Self {
+        let interval = interval.max(Duration::from_millis(1));
+        Self {
+            kind,
+            interval,
+            min_pool,
+            fraction: fraction.clamp(0.0, 1.0),
+            pool: vec![],
+            next_flush: Instant::now() + interval,
+        }
+    }
+}
+
+impl MixStrategy for PoolMix {
+    fn admit(
+        &mut self,
+        packet: Packet,
+        _delay: Duration,
+        _rng: &mut StdRng,
+    ) -> Vec<(Duration, Packet)> {
+        self.pool.push(packet);
+        vec![]
+    }
+
+    fn next_flush(&self) -> Option<Instant> {
+        Some(self.next_flush)
+    }
+
+    fn flush(&mut self, rng: &mut StdRng) -> Vec<Packet> {
+        self.next_flush = Instant::now() + self.interval;
+        let n = self.pool.len();
+        let spare = n.saturating_sub(self.min_pool);
+        let count = spare.min((n as f64 * self.fraction).ceil() as usize);
+        if count == 0 {
+            return vec![];
+        }
+        self.pool.shuffle(rng);
+        match self.kind {
+            PoolKind::Cottrell => self.pool.split_off(n - count),
+            PoolKind::Binomial => {
+                let probability = count as f64 / n as f64;
+                let (mut sent, mut kept): (Vec<Packet>, Vec<Packet>) =
+                    std::mem::take(&mut self.pool)
+                        .into_iter()
+                        .partition(|_| rng.random_bool(probability));
+                // Packets drawn beyond what the pool can spare stay in it
+                kept.extend(sent.drain(spare.min(sent.len())..));
+                self.pool = kept;
+                sent
+            }
+        }
+    }
+}
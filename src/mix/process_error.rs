@@ -0,0 +1,34 @@
+use std::{error::Error, fmt::Display};
+
+use sphinx_packet::Error as SphinxError;
+
+#[derive(Debug)]
+pub enum ProcessError {
+    // A packet with the same header was processed before
+    Replayed,
+    Sphinx(SphinxError),
+}
+
+impl Error for ProcessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProcessError::Replayed => None,
+            ProcessError::Sphinx(e) => Some(e),
+        }
+    }
+}
+
+impl Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessError::Replayed => write!(f, "packet was replayed"),
+            ProcessError::Sphinx(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<SphinxError> for ProcessError {
+    fn from(e: SphinxError) -> Self {
+        ProcessError::Sphinx(e)
+    }
+}
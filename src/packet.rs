@@ -1,20 +1,87 @@
 use std::fmt::Display;
 
+use prometheus_client::encoding::EncodeLabelValue;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sphinx_packet::SphinxPacket;
-use uuid::Uuid;
+use uuid::Builder as UuidBuilder;
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize, EncodeLabelValue)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverKind {
+    // Routed back to its sender
+    Loop,
+    // Sent to a random client, which discards it
+    Drop,
+}
+
+// What a packet carries; only the recipient can tell from the
+// decrypted payload, so forwarding nodes never act on this, and it is
+// kept alongside packets purely so that cover traffic, ACKs and copies
+// of retransmitted messages stay out of the run's drop counts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketKind {
+    #[default]
+    Message,
+    // A message whose sender waits for it to be acknowledged, and
+    // retransmits it otherwise
+    ReliableMessage,
+    Ack,
+    // An answer sent back through a SURB of the message it answers
+    Reply,
+    // One piece of the message with the given id, which was too large
+    // for a single packet
+    Fragment(u64),
+    Cover(CoverKind),
+    // A copy of another packet sent by the adversary
+    Replay,
+}
+
+impl PacketKind {
+    pub fn is_message(&self) -> bool {
+        *self == PacketKind::Message
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Message {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub from: Option<String>,
     pub body: String,
+    // Time since the start of the run at which the message was sent
+    pub sent_at_micros: u64,
+    #[serde(default, skip_serializing_if = "PacketKind::is_message")]
+    pub kind: PacketKind,
+    // Base64-encoded single-use reply block the recipient can answer
+    // through without learning where it leads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub surb: Option<String>,
+    // Set on messages of kind `Fragment`, whose body is left empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment: Option<Fragment>,
+}
+
+// A slice of a serialised message, carried by a message of its own
+#[derive(Serialize, Deserialize)]
+pub struct Fragment {
+    pub index: usize,
+    pub count: usize,
+    // Fragments it takes to put the message back together, fewer than
+    // `count` if the message is erasure coded
+    pub needed: usize,
+    // Length of the whole serialised message, which the last data
+    // fragment is padded beyond
+    pub length: usize,
+    // Base64-encoded bytes of the slice
+    pub data: String,
 }
 
 pub struct Packet {
     id: String,
     to: String,
     from: String,
+    kind: PacketKind,
     body: SphinxPacket,
 }
 
@@ -25,8 +92,9 @@ impl Display for Packet {
 }
 
 impl Packet {
-    pub fn new(to: &str, from: &str, body: SphinxPacket) -> Self {
-        Self::new_with_id(&Uuid::new_v4().to_string(), to, from, body)
+    pub fn new<R: Rng>(to: &str, from: &str, body: SphinxPacket, rng: &mut R) -> Self {
+        let id = UuidBuilder::from_random_bytes(rng.random()).into_uuid();
+        Self::new_with_id(&id.to_string(), to, from, body)
     }
 
     pub fn new_with_id(id: &str, to: &str, from: &str, body: SphinxPacket) -> Self {
@@ -34,10 +102,16 @@ impl Packet {
             id: id.to_owned(),
             to: to.to_owned(),
             from: from.to_owned(),
+            kind: PacketKind::Message,
             body,
         }
     }
 
+    pub fn with_kind(mut self, kind: PacketKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -50,6 +124,10 @@ impl Packet {
         &self.from
     }
 
+    pub fn kind(&self) -> PacketKind {
+        self.kind
+    }
+
     pub fn body(&self) -> &SphinxPacket {
         &self.body
     }
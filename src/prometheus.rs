@@ -1,10 +1,25 @@
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
 use prometheus_client::{
     encoding::{text::encode, EncodeLabelSet, EncodeLabelValue},
-    metrics::{counter::Counter, family::Family},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
     registry::Registry,
 };
 use tiny_http::Response;
-use tokio::task::JoinHandle;
+
+use crate::packet::CoverKind;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct MessageLabels {
@@ -19,19 +34,167 @@ pub enum MessageStatus {
     Received,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct CoverLabels {
+    pub client: String,
+    pub kind: CoverKind,
+    pub status: MessageStatus,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct NodeLabels {
+    pub node: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct LoopLabels {
+    pub node: String,
+    pub status: LoopStatus,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum LoopStatus {
+    Sent,
+    Returned,
+    Lost,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct SlotLabels {
+    pub client: String,
+    pub kind: SlotKind,
+}
+
+// What a constant-rate client sent in a slot
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum SlotKind {
+    Real,
+    Dummy,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct SurbLabels {
+    pub client: String,
+    pub status: SurbStatus,
+}
+
+// What happened to a SURB at a client
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum SurbStatus {
+    // Attached to a message the client sent
+    Attached,
+    // Came with a message the client received
+    Received,
+    // Replied through by the client
+    Used,
+    // Brought a reply back to the client
+    Returned,
+    // Could not be built, decoded or replied through, or brought back
+    // a reply the client was not waiting for
+    Failed,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FragmentLabels {
+    pub client: String,
+    pub status: FragmentStatus,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum FragmentStatus {
+    Sent,
+    Received,
+    // Still missing when its recipient gave up on the message
+    Lost,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ReassemblyLabels {
+    pub client: String,
+    pub status: ReassemblyStatus,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ReassemblyStatus {
+    Completed,
+    TimedOut,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ReplayLabels {
+    pub node: String,
+    pub status: ReplayStatus,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ReplayStatus {
+    // Sent to the node by the adversary
+    Injected,
+    // Refused by the node as a packet it had already processed
+    Dropped,
+    // Delivered to the recipient, which had not processed the original
+    Accepted,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ChannelDropLabels {
+    pub node: String,
+    pub reason: DropReason,
+}
+
+// Which packet a full queue dropped, following its overflow policy
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum DropReason {
+    Newest,
+    Oldest,
+    Random,
+}
+
 pub struct MetricFamilies {
     pub messages: Family<MessageLabels, Counter>,
+    pub cover: Family<CoverLabels, Counter>,
+    pub mix_loops: Family<LoopLabels, Counter>,
+    pub mix_loop_loss: Family<NodeLabels, Gauge<f64, AtomicU64>>,
+    pub mix_loop_alerts: Family<NodeLabels, Counter>,
+    pub send_slots: Family<SlotLabels, Counter>,
+    pub send_queue_length: Family<NodeLabels, Gauge>,
+    pub send_queue_delay: Family<NodeLabels, Histogram, fn() -> Histogram>,
+    pub surbs: Family<SurbLabels, Counter>,
+    pub node_online: Family<NodeLabels, Gauge>,
+    pub fragments: Family<FragmentLabels, Counter>,
+    pub reassemblies: Family<ReassemblyLabels, Counter>,
+    pub replays: Family<ReplayLabels, Counter>,
+    pub channel_drops: Family<ChannelDropLabels, Counter>,
     // pub messages_sent: Family<MessageLabels, Counter>,
     // pub messages_received: Family<MessageLabels, Counter>,
 }
 
-pub fn setup() -> (MetricFamilies, JoinHandle<()>) {
+// Starts serving metrics at the given address, failing if it cannot
+// be bound
+pub fn setup(
+    address: &str,
+) -> Result<(MetricFamilies, MetricsServer), Box<dyn Error + Send + Sync>> {
     let mut registry = <Registry>::default();
 
     let mf = MetricFamilies {
         // messages_sent: Family::<MessageLabels, Counter>::default(),
         // messages_received: Family::<MessageLabels, Counter>::default(),
         messages: Family::<MessageLabels, Counter>::default(),
+        cover: Family::<CoverLabels, Counter>::default(),
+        mix_loops: Family::<LoopLabels, Counter>::default(),
+        mix_loop_loss: Family::<NodeLabels, Gauge<f64, AtomicU64>>::default(),
+        mix_loop_alerts: Family::<NodeLabels, Counter>::default(),
+        send_slots: Family::<SlotLabels, Counter>::default(),
+        send_queue_length: Family::<NodeLabels, Gauge>::default(),
+        send_queue_delay: Family::new_with_constructor(|| {
+            Histogram::new(exponential_buckets(0.01, 2.0, 16))
+        }),
+        surbs: Family::<SurbLabels, Counter>::default(),
+        node_online: Family::<NodeLabels, Gauge>::default(),
+        fragments: Family::<FragmentLabels, Counter>::default(),
+        reassemblies: Family::<ReassemblyLabels, Counter>::default(),
+        replays: Family::<ReplayLabels, Counter>::default(),
+        channel_drops: Family::<ChannelDropLabels, Counter>::default(),
     };
 
     // registry.register(
@@ -49,10 +212,81 @@ pub fn setup() -> (MetricFamilies, JoinHandle<()>) {
         "Messages sent through system",
         mf.messages.clone(),
     );
+    registry.register(
+        "cover_messages",
+        "Loop and drop cover sent and received by clients",
+        mf.cover.clone(),
+    );
+    registry.register(
+        "mix_loops",
+        "Loops sent by mix nodes, and whether they returned",
+        mf.mix_loops.clone(),
+    );
+    registry.register(
+        "mix_loop_loss",
+        "Share of each mix node's recent loops that were lost",
+        mf.mix_loop_loss.clone(),
+    );
+    registry.register(
+        "mix_loop_alerts",
+        "Times a mix node's loop loss rose above its alert threshold",
+        mf.mix_loop_alerts.clone(),
+    );
+    registry.register(
+        "send_slots",
+        "Sending slots of constant-rate clients, by whether they carried a real message or a dummy",
+        mf.send_slots.clone(),
+    );
+    registry.register(
+        "send_queue_length",
+        "Messages waiting for a sending slot at each constant-rate client",
+        mf.send_queue_length.clone(),
+    );
+    registry.register(
+        "send_queue_delay_seconds",
+        "Time messages spent waiting for a sending slot",
+        mf.send_queue_delay.clone(),
+    );
+    registry.register(
+        "surbs",
+        "SURBs attached, received and replied through by clients, and SURB failures",
+        mf.surbs.clone(),
+    );
+    registry.register(
+        "node_online",
+        "Whether each node with an availability model is online (1) or offline (0)",
+        mf.node_online.clone(),
+    );
+    registry.register(
+        "fragments",
+        "Message fragments sent and received by clients, and those lost",
+        mf.fragments.clone(),
+    );
+    registry.register(
+        "reassemblies",
+        "Fragmented messages put back together by their recipients, or given up on",
+        mf.reassemblies.clone(),
+    );
+    registry.register(
+        "replays",
+        "Replayed packets sent to each node by the adversary, and those the node refused or, as their recipient, accepted",
+        mf.replays.clone(),
+    );
+    registry.register(
+        "channel_drops",
+        "Packets dropped by each node's full queue, by which packet its overflow policy gave up",
+        mf.channel_drops.clone(),
+    );
 
-    let server = tiny_http::Server::http("0.0.0.0:5050").unwrap();
-    let server_handle = tokio::spawn(async move {
-        loop {
+    // Serve from a dedicated thread since receiving requests blocks,
+    // which would otherwise stall the runtime (and, with a simulated
+    // clock, keep time from ever advancing)
+    let server = Arc::new(tiny_http::Server::http(address)?);
+    let stopping = Arc::new(AtomicBool::new(false));
+    let handle = thread::spawn({
+        let server = server.clone();
+        let stopping = stopping.clone();
+        move || loop {
             match server.recv() {
                 Ok(req) => {
                     let mut buffer = String::new();
@@ -65,10 +299,36 @@ pub fn setup() -> (MetricFamilies, JoinHandle<()>) {
                         Err(e) => eprintln!("[METRICS] Failed encoding: {e}"),
                     };
                 }
+                Err(_) if stopping.load(Ordering::SeqCst) => return,
                 Err(e) => eprintln!("[METRICS] Failed receiving: {e}"),
             };
         }
     });
 
-    (mf, server_handle)
+    Ok((
+        mf,
+        MetricsServer {
+            server,
+            stopping,
+            handle: Some(handle),
+        },
+    ))
+}
+
+// Serves the metrics until dropped, at which point it stops listening
+// and frees its address
+pub struct MetricsServer {
+    server: Arc<tiny_http::Server>,
+    stopping: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.server.unblock();
+        if let Some(Err(_)) = self.handle.take().map(JoinHandle::join) {
+            eprintln!("[METRICS] Server thread panicked");
+        }
+    }
 }
@@ -0,0 +1,5 @@
+mod provider;
+mod provider_command;
+
+pub use provider::Provider;
+pub use provider_command::ProviderCommand;
@@ -0,0 +1,236 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use prometheus_client::metrics::{counter::Counter, family::Family};
+use rand::Rng;
+use sphinx_packet::ProcessedPacketData;
+use tokio::{
+    sync::mpsc::Sender as MpscSender,
+    time::{sleep_until, Instant},
+};
+use x25519_dalek::StaticSecret;
+
+use crate::{
+    bytes::bytes_to_string_truncate_zeroes,
+    config::Network,
+    directory::{DirectoryCommand, DirectoryRegistration, NodeRole},
+    mix::{NodeKeys, ProcessError},
+    packet::Packet,
+    prometheus::{MetricFamilies, ReplayLabels, ReplayStatus},
+    provider::ProviderCommand,
+    queue::{self, Overflow, QueueReceiver, QueueSender},
+    registration::{register_node, rekey_node},
+    rng::{RngStream, Rngs},
+    server::{NodeTx, ServerCommand},
+    stats::Stats,
+};
+
+// Last hop before a client, which keeps the client's packets in a
+// mailbox until the client comes online and fetches them; packets stay
+// Sphinx encrypted for the client, so the provider learns who receives
+// a message but not what it says
+pub struct Provider {
+    id: String,
+    keys: NodeKeys,
+    mailboxes: HashMap<String, VecDeque<Packet>>,
+    directory_tx: MpscSender<DirectoryCommand>,
+    provider_tx: QueueSender<ProviderCommand>,
+    provider_rx: QueueReceiver<ProviderCommand>,
+    replays: Option<Family<ReplayLabels, Counter>>,
+    stats: Stats,
+}
+
+impl Provider {
+    pub fn new(
+        id: &str,
+        directory_tx: MpscSender<DirectoryCommand>,
+        buffer_size: usize,
+        rngs: &Rngs,
+        stats: Stats,
+        network: &Network,
+    ) -> Self {
+        let (provider_tx, provider_rx) = queue::channel::<ProviderCommand>(buffer_size);
+        let sk = StaticSecret::from(rngs.stream(RngStream::Keys, id).random::<[u8; 32]>());
+        Self {
+            id: id.to_owned(),
+            keys: NodeKeys::new(
+                sk,
+                network.key_rotation_millis.map(Duration::from_millis),
+                rngs.stream(RngStream::KeyRotation, id),
+            ),
+            mailboxes: HashMap::new(),
+            directory_tx,
+            provider_tx,
+            provider_rx,
+            replays: None,
+            stats,
+        }
+    }
+
+    // Has the provider drop packets when its queue is full, according
+    // to the overflow policy, rather than have the server drop
+    // whichever packet it cannot fit
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.provider_rx.set_overflow(overflow);
+        self
+    }
+
+    // Has the provider report the replays it refuses to Prometheus
+    pub fn with_metrics(mut self, mf: &MetricFamilies) -> Self {
+        self.replays = Some(mf.replays.clone());
+        self
+    }
+
+    pub async fn listen(&mut self, server_tx: QueueSender<ServerCommand>) {
+        let registration = DirectoryRegistration {
+            id: self.id.clone(),
+            pk: self.keys.public_key(),
+            role: NodeRole::Provider,
+            capacity: 1.0,
+            latency: Duration::ZERO,
+            // Packets are held until fetched rather than delayed
+            average_delay: Duration::ZERO,
+            provider: None,
+        };
+        let tx = NodeTx::Provider(self.provider_tx.clone());
+        if !register_node(
+            "[PROVIDER]",
+            tx,
+            registration,
+            &server_tx,
+            &self.directory_tx,
+        )
+        .await
+        {
+            return;
+        }
+
+        println!("[PROVIDER][{}] Starting listening", &self.id);
+        loop {
+            let next_rotation = self.keys.next_rotation();
+            let cmd = tokio::select! {
+                cmd = self.provider_rx.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => return,
+                },
+                _ = async {
+                    match next_rotation {
+                        Some(next_rotation) => sleep_until(next_rotation).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.rotate_keys().await;
+                    continue;
+                }
+            };
+            match cmd {
+                ProviderCommand::Shutdown => {
+                    return;
+                }
+                ProviderCommand::ReceivePacket(packet) => {
+                    let kind = packet.kind();
+                    let (packet_id, _, from, sphinx_packet) = packet.take();
+                    match self.keys.process(sphinx_packet) {
+                        // Providers do not mix, so the packet is stored
+                        // straight away regardless of its delay
+                        Ok(packet) => match packet.data {
+                            ProcessedPacketData::ForwardHop {
+                                next_hop_packet,
+                                next_hop_address,
+                                delay: _,
+                            } => {
+                                let to =
+                                    bytes_to_string_truncate_zeroes(next_hop_address.as_bytes())
+                                        .to_string();
+                                let packet =
+                                    Packet::new_with_id(&packet_id, &to, &self.id, next_hop_packet)
+                                        .with_kind(kind);
+                                let mailbox = self.mailboxes.entry(to.clone()).or_default();
+                                mailbox.push_back(packet);
+                                println!(
+                                    "[PROVIDER][{}] Stored packet from \"{from}\" in mailbox of \"{to}\" ({} waiting)",
+                                    &self.id,
+                                    mailbox.len()
+                                );
+                            }
+                            ProcessedPacketData::FinalHop { .. } => {
+                                eprintln!(
+                                    "[PROVIDER][{}] Providers are never the destination of a message, dropping packet from \"{from}\"",
+                                    &self.id
+                                );
+                                self.stats.record_dropped(kind);
+                            }
+                        },
+                        Err(ProcessError::Replayed) => {
+                            eprintln!(
+                                "[PROVIDER][{}] Dropping replayed packet from \"{from}\"",
+                                &self.id
+                            );
+                            self.record_replay();
+                        }
+                        Err(e) => {
+                            eprintln!(
+                                "[PROVIDER][{}] Failed to process Sphinx packet from \"{from}\": {e}",
+                                &self.id
+                            );
+                            self.stats.record_dropped(kind);
+                        }
+                    }
+                }
+                ProviderCommand::FetchMailbox(id) => {
+                    let mailbox = self.mailboxes.remove(&id).unwrap_or_default();
+                    if !mailbox.is_empty() {
+                        println!(
+                            "[PROVIDER][{}] Handing {} packets over to \"{id}\"",
+                            &self.id,
+                            mailbox.len()
+                        );
+                    }
+                    for packet in mailbox {
+                        let kind = packet.kind();
+                        if let Err(e) = server_tx.send(ServerCommand::Send(packet)).await {
+                            eprintln!(
+                                "[PROVIDER][{}] Unable to hand packet over to \"{id}\": {e}",
+                                &self.id
+                            );
+                            self.stats.record_dropped(kind);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Moves on to a fresh Sphinx key and publishes it in the directory
+    async fn rotate_keys(&mut self) {
+        let cleared = self.keys.rotate(Instant::now());
+        println!(
+            "[PROVIDER][{}] Rotated keys, clearing {cleared} replay tags",
+            &self.id
+        );
+        rekey_node(
+            "[PROVIDER]",
+            &self.id,
+            self.keys.public_key(),
+            &self.directory_tx,
+        )
+        .await;
+    }
+
+    fn record_replay(&self) {
+        if let Some(replays) = &self.replays {
+            replays
+                .get_or_create(&ReplayLabels {
+                    node: self.id.clone(),
+                    status: ReplayStatus::Dropped,
+                })
+                .inc();
+        }
+    }
+
+    pub fn get_tx(&self) -> QueueSender<ProviderCommand> {
+        self.provider_tx.clone()
+    }
+}
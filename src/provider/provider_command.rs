@@ -0,0 +1,18 @@
+use crate::{packet::Packet, queue::Droppable};
+
+pub enum ProviderCommand {
+    ReceivePacket(Packet),
+    // Hands every packet in the mailbox of the client with this id
+    // over to that client
+    FetchMailbox(String),
+    Shutdown,
+}
+
+impl Droppable for ProviderCommand {
+    fn packet(&self) -> Option<&Packet> {
+        match self {
+            ProviderCommand::ReceivePacket(packet) => Some(packet),
+            _ => None,
+        }
+    }
+}
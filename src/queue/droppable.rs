@@ -0,0 +1,8 @@
+use crate::packet::Packet;
+
+// Command sent over an actor's queue, which the queue may drop to make
+// room when it is full if the command carries a packet; anything else,
+// such as a registration or a mailbox fetch, always waits for room
+pub trait Droppable {
+    fn packet(&self) -> Option<&Packet>;
+}
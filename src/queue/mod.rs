@@ -0,0 +1,7 @@
+mod droppable;
+mod overflow;
+mod queue;
+
+pub use droppable::Droppable;
+pub use overflow::Overflow;
+pub use queue::{channel, QueueReceiver, QueueSender};
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use prometheus_client::metrics::{counter::Counter, family::Family};
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    config::OverflowPolicy,
+    prometheus::{ChannelDropLabels, DropReason, MetricFamilies},
+    queue::Droppable,
+    stats::Stats,
+};
+
+// What a node's queue does with a packet that arrives while the queue
+// is full, and the accounting of the packets it drops as a result
+pub struct Overflow {
+    id: String,
+    log_prefix: String,
+    policy: OverflowPolicy,
+    drops: Option<Family<ChannelDropLabels, Counter>>,
+    rng: StdRng,
+    stats: Stats,
+}
+
+impl Overflow {
+    pub fn new(
+        id: &str,
+        log_prefix: &str,
+        policy: OverflowPolicy,
+        rng: StdRng,
+        stats: Stats,
+    ) -> Self {
+        Self {
+            id: id.to_owned(),
+            log_prefix: log_prefix.to_owned(),
+            policy,
+            drops: None,
+            rng,
+            stats,
+        }
+    }
+
+    // Has the queue report the packets it drops to Prometheus
+    pub fn with_metrics(mut self, mf: &MetricFamilies) -> Self {
+        self.drops = Some(mf.channel_drops.clone());
+        self
+    }
+
+    // Makes room in a full queue for the item by dropping a packet,
+    // which may be the item itself; hands the item back if nothing can
+    // be dropped, in which case the sender has to wait
+    pub fn make_room<T: Droppable>(&mut self, queue: &mut VecDeque<T>, item: T) -> Result<(), T> {
+        let queued = queue
+            .iter()
+            .enumerate()
+            .filter(|(_, queued)| queued.packet().is_some())
+            .map(|(index, _)| index)
+            .collect::<Vec<usize>>();
+        let droppable = item.packet().is_some();
+        let (reason, evict) = match self.policy {
+            OverflowPolicy::Block => return Err(item),
+            // The item, or the newest waiting packet to make room for
+            // a command that cannot be dropped
+            OverflowPolicy::DropNewest => (
+                DropReason::Newest,
+                queued.last().copied().filter(|_| !droppable),
+            ),
+            // Oldest waiting packet, or the item if no packet is waiting
+            OverflowPolicy::DropOldest => (DropReason::Oldest, queued.first().copied()),
+            // Any of the waiting packets or the item, uniformly
+            OverflowPolicy::DropRandom => {
+                let candidates = queued.len() + droppable as usize;
+                if candidates == 0 {
+                    return Err(item);
+                }
+                let pick = self.rng.random_range(0..candidates);
+                (DropReason::Random, queued.get(pick).copied())
+            }
+        };
+        match evict.and_then(|index| queue.remove(index)) {
+            Some(evicted) => {
+                self.record(&evicted, reason);
+                queue.push_back(item);
+                Ok(())
+            }
+            None if droppable => {
+                self.record(&item, reason);
+                Ok(())
+            }
+            None => Err(item),
+        }
+    }
+
+    fn record<T: Droppable>(&self, item: &T, reason: DropReason) {
+        let Some(packet) = item.packet() else {
+            return;
+        };
+        println!(
+            "{}[{}] Queue is full, dropping packet \"{}\" from \"{}\"",
+            &self.log_prefix,
+            &self.id,
+            packet.id(),
+            packet.from()
+        );
+        self.stats.record_dropped(packet.kind());
+        if let Some(drops) = &self.drops {
+            drops
+                .get_or_create(&ChannelDropLabels {
+                    node: self.id.clone(),
+                    reason,
+                })
+                .inc();
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{
+    mpsc::error::{SendError, TrySendError},
+    Notify,
+};
+
+use crate::queue::{Droppable, Overflow};
+
+// Bounded multi-producer, single-consumer queue into an actor; unlike
+// a tokio channel, a full queue can drop packets it already holds to
+// make room, according to its overflow policy, and only blocks the
+// sender if it has no policy or nothing it may drop
+pub fn channel<T: Droppable>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            overflow: None,
+            senders: 1,
+            closed: false,
+        }),
+        capacity: capacity.max(1),
+        item_sent: Notify::new(),
+        room_made: Notify::new(),
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    // Wakes the receiver once an item is queued or the last sender is
+    // dropped
+    item_sent: Notify,
+    // Wakes blocked senders once an item is taken off the queue or the
+    // receiver is dropped
+    room_made: Notify,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    overflow: Option<Overflow>,
+    senders: usize,
+    closed: bool,
+}
+
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Droppable> QueueSender<T> {
+    pub async fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let mut item = item;
+        loop {
+            // Registers for wakeups before checking for room, so room
+            // made in between is never missed
+            let room_made = self.shared.room_made.notified();
+            tokio::pin!(room_made);
+            room_made.as_mut().enable();
+            match self.try_send(item) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(unsent)) => return Err(SendError(unsent)),
+                Err(TrySendError::Full(unsent)) => item = unsent,
+            }
+            room_made.await;
+        }
+    }
+
+    // Queues the item if there is room, or the overflow policy makes
+    // some, handing it back otherwise instead of waiting
+    pub fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        let Ok(mut state) = self.shared.state.lock() else {
+            return Err(TrySendError::Closed(item));
+        };
+        if state.closed {
+            return Err(TrySendError::Closed(item));
+        }
+        let State {
+            items, overflow, ..
+        } = &mut *state;
+        let queued = if items.len() < self.shared.capacity {
+            items.push_back(item);
+            Ok(())
+        } else {
+            match overflow {
+                Some(overflow) => overflow.make_room(items, item),
+                None => Err(item),
+            }
+        };
+        drop(state);
+        match queued {
+            Ok(()) => {
+                self.shared.item_sent.notify_one();
+                Ok(())
+            }
+            Err(unsent) => Err(TrySendError::Full(unsent)),
+        }
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.senders += 1;
+        }
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.senders -= 1;
+            if state.senders == 0 {
+                self.shared.item_sent.notify_one();
+            }
+        }
+    }
+}
+
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueReceiver<T> {
+    // Lets the queue drop packets when full instead of blocking senders
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.overflow = Some(overflow);
+        }
+    }
+
+    // Takes the next item off the queue, waiting for one if it is
+    // empty; resolves to None once it is empty and every sender has
+    // been dropped
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let item_sent = self.shared.item_sent.notified();
+            tokio::pin!(item_sent);
+            item_sent.as_mut().enable();
+            {
+                let mut state = self.shared.state.lock().ok()?;
+                if let Some(item) = state.items.pop_front() {
+                    drop(state);
+                    self.shared.room_made.notify_one();
+                    return Some(item);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            item_sent.await;
+        }
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.closed = true;
+        }
+        self.shared.room_made.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use sphinx_packet::{
+        header::delays::Delay,
+        packet::builder::SphinxPacketBuilder,
+        route::{Destination, DestinationAddressBytes, Node, NodeAddressBytes},
+    };
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    use super::*;
+    use crate::{config::OverflowPolicy, packet::Packet, stats::Stats};
+
+    enum Command {
+        Packet(Packet),
+        Register(String),
+    }
+
+    impl Droppable for Command {
+        fn packet(&self) -> Option<&Packet> {
+            match self {
+                Command::Packet(packet) => Some(packet),
+                Command::Register(_) => None,
+            }
+        }
+    }
+
+    fn packet(id: &str) -> Command {
+        let sk = StaticSecret::from([1u8; 32]);
+        let node = Node::new(
+            NodeAddressBytes::from_bytes([2u8; 32]),
+            PublicKey::from(&sk),
+        );
+        let destination = Destination::new(DestinationAddressBytes::from_bytes([2u8; 32]), [0; 16]);
+        let body = SphinxPacketBuilder::new()
+            .with_initial_secret(&StaticSecret::from([3u8; 32]))
+            .build_packet(b"test", &[node], &destination, &[Delay::new_from_nanos(0)])
+            .unwrap();
+        Command::Packet(Packet::new_with_id(id, "to", "from", body))
+    }
+
+    fn register(id: &str) -> Command {
+        Command::Register(id.to_owned())
+    }
+
+    fn queue(
+        capacity: usize,
+        policy: Option<OverflowPolicy>,
+        seed: u64,
+    ) -> (QueueSender<Command>, QueueReceiver<Command>) {
+        let (tx, mut rx) = channel(capacity);
+        if let Some(policy) = policy {
+            rx.set_overflow(Overflow::new(
+                "node",
+                "[TEST]",
+                policy,
+                StdRng::seed_from_u64(seed),
+                Stats::new(None),
+            ));
+        }
+        (tx, rx)
+    }
+
+    // Sends every item, then closes the queue and lists what is left
+    async fn send_all(
+        (tx, mut rx): (QueueSender<Command>, QueueReceiver<Command>),
+        items: Vec<Command>,
+    ) -> Vec<String> {
+        for item in items {
+            tx.send(item).await.unwrap();
+        }
+        drop(tx);
+        let mut ids = vec![];
+        while let Some(item) = rx.recv().await {
+            ids.push(match item {
+                Command::Packet(packet) => packet.id().to_owned(),
+                Command::Register(id) => id,
+            });
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn drop_newest_drops_incoming_packet() {
+        let ids = send_all(
+            queue(2, Some(OverflowPolicy::DropNewest), 0),
+            vec![packet("a"), packet("b"), packet("c")],
+        )
+        .await;
+        assert_eq!(ids, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_makes_room_for_command() {
+        let ids = send_all(
+            queue(2, Some(OverflowPolicy::DropNewest), 0),
+            vec![packet("a"), packet("b"), register("r")],
+        )
+        .await;
+        assert_eq!(ids, ["a", "r"]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_drops_oldest_packet() {
+        let ids = send_all(
+            queue(3, Some(OverflowPolicy::DropOldest), 0),
+            vec![register("r"), packet("a"), packet("b"), packet("c")],
+        )
+        .await;
+        assert_eq!(ids, ["r", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn drop_random_drops_any_packet() {
+        let mut dropped = vec![];
+        for seed in 0..32 {
+            let ids = send_all(
+                queue(3, Some(OverflowPolicy::DropRandom), seed),
+                vec![register("r"), packet("a"), packet("b"), packet("c")],
+            )
+            .await;
+            assert_eq!(ids.len(), 3);
+            assert_eq!(ids[0], "r");
+            dropped.extend(
+                ["a", "b", "c"]
+                    .into_iter()
+                    .filter(|id| !ids.iter().any(|kept| kept == id)),
+            );
+        }
+        for id in ["a", "b", "c"] {
+            assert!(dropped.contains(&id), "\"{id}\" never dropped");
+        }
+    }
+
+    #[tokio::test]
+    async fn command_waits_for_room() {
+        let (tx, mut rx) = queue(2, Some(OverflowPolicy::DropOldest), 0);
+        tx.send(register("r1")).await.unwrap();
+        tx.send(register("r2")).await.unwrap();
+        let sender = tokio::spawn(async move { tx.send(register("r3")).await.is_ok() });
+        tokio::task::yield_now().await;
+        assert!(!sender.is_finished());
+        assert!(matches!(rx.recv().await, Some(Command::Register(id)) if id == "r1"));
+        assert!(sender.await.unwrap());
+        assert!(matches!(rx.recv().await, Some(Command::Register(id)) if id == "r2"));
+        assert!(matches!(rx.recv().await, Some(Command::Register(id)) if id == "r3"));
+    }
+
+    #[tokio::test]
+    async fn blocked_sender_wakes_after_recv() {
+        let (tx, mut rx) = queue(1, Some(OverflowPolicy::Block), 0);
+        tx.send(packet("a")).await.unwrap();
+        let sender = tokio::spawn(async move { tx.send(packet("b")).await.is_ok() });
+        tokio::task::yield_now().await;
+        assert!(!sender.is_finished());
+        assert!(matches!(rx.recv().await, Some(Command::Packet(packet)) if packet.id() == "a"));
+        assert!(sender.await.unwrap());
+        assert!(matches!(rx.recv().await, Some(Command::Packet(packet)) if packet.id() == "b"));
+    }
+
+    #[tokio::test]
+    async fn recv_ends_after_last_sender_drops() {
+        let (tx, mut rx) = queue(2, None, 0);
+        let other_tx = tx.clone();
+        tx.send(register("r")).await.unwrap();
+        drop(tx);
+        let receiver = tokio::spawn(async move {
+            let first = rx.recv().await.is_some();
+            let second = rx.recv().await.is_none();
+            first && second
+        });
+        tokio::task::yield_now().await;
+        assert!(!receiver.is_finished());
+        drop(other_tx);
+        assert!(receiver.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn send_fails_after_receiver_drops() {
+        let (tx, rx) = queue(1, None, 0);
+        drop(rx);
+        assert!(tx.send(register("r")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn try_send_hands_back_item_without_room() {
+        let (tx, mut rx) = queue(1, Some(OverflowPolicy::Block), 0);
+        assert!(tx.try_send(packet("a")).is_ok());
+        assert!(matches!(
+            tx.try_send(packet("b")),
+            Err(TrySendError::Full(Command::Packet(packet))) if packet.id() == "b"
+        ));
+        assert!(matches!(rx.recv().await, Some(Command::Packet(packet)) if packet.id() == "a"));
+        drop(rx);
+        assert!(matches!(
+            tx.try_send(register("r")),
+            Err(TrySendError::Closed(_))
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+use tokio::sync::mpsc::{self, Sender as MpscSender};
+use x25519_dalek::PublicKey;
+
+use crate::{
+    directory::{DirectoryCommand, DirectoryRegistration, DirectoryRegistrationError},
+    queue::QueueSender,
+    server::{NodeTx, ServerCommand, ServerRegistration, ServerRegistrationError},
+};
+
+// Registers a node other than a client at the server, so that packets
+// reach it, and in the directory, so that clients can route through
+// it; returns whether both succeeded
+pub async fn register_node(
+    log_prefix: &str,
+    tx: NodeTx,
+    registration: DirectoryRegistration,
+    server_tx: &QueueSender<ServerCommand>,
+    directory_tx: &MpscSender<DirectoryCommand>,
+) -> bool {
+    let id = registration.id.clone();
+    let (response_tx, mut response_rx) = mpsc::channel::<Result<(), ServerRegistrationError>>(1);
+    let cmd = ServerCommand::Register(
+        ServerRegistration {
+            id: id.clone(),
+            tx: Some(tx),
+        },
+        response_tx,
+    );
+    if let Err(e) = server_tx.send(cmd).await {
+        eprintln!("{log_prefix}[{id}] Failed to send registration request: {e}");
+        return false;
+    }
+    match response_rx.recv().await {
+        Some(Ok(_)) => {}
+        Some(Err(e)) => {
+            eprintln!("{log_prefix}[{id}] Failed to register at server: {e}");
+            return false;
+        }
+        None => {
+            eprintln!(
+                "{log_prefix}[{id}] Registration response channel closed before receiving anything"
+            );
+            return false;
+        }
+    }
+
+    register_in_directory(log_prefix, registration, directory_tx).await
+}
+
+// Adds a node's entry to the directory, as when it first starts or
+// comes back online after deregistering; returns whether it succeeded
+pub async fn register_in_directory(
+    log_prefix: &str,
+    registration: DirectoryRegistration,
+    directory_tx: &MpscSender<DirectoryCommand>,
+) -> bool {
+    let id = registration.id.clone();
+    let (response_tx, mut response_rx) = mpsc::channel::<Result<(), DirectoryRegistrationError>>(1);
+    let cmd = DirectoryCommand::Register(registration, response_tx);
+    if let Err(e) = directory_tx.send(cmd).await {
+        eprintln!("{log_prefix}[{id}] Failed to send registration request: {e}");
+        return false;
+    }
+    match response_rx.recv().await {
+        Some(Ok(_)) => true,
+        Some(Err(e)) => {
+            eprintln!("{log_prefix}[{id}] Failed to register at directory: {e}");
+            false
+        }
+        None => {
+            eprintln!(
+                "{log_prefix}[{id}] Registration response channel closed before receiving anything"
+            );
+            false
+        }
+    }
+}
+
+// Publishes the key a node has rotated to, so that clients building
+// routes after their next directory lookup use it
+pub async fn rekey_node(
+    log_prefix: &str,
+    id: &str,
+    pk: PublicKey,
+    directory_tx: &MpscSender<DirectoryCommand>,
+) {
+    let cmd = DirectoryCommand::Rekey(id.to_owned(), pk);
+    if let Err(e) = directory_tx.send(cmd).await {
+        eprintln!("{log_prefix}[{id}] Failed to send new key to directory: {e}");
+    }
+}
+
+// Removes a node's entry from the directory while it is offline, so
+// that clients looking up the directory stop routing through it
+pub async fn deregister_node(
+    log_prefix: &str,
+    id: &str,
+    directory_tx: &MpscSender<DirectoryCommand>,
+) {
+    let cmd = DirectoryCommand::Deregister(id.to_owned());
+    if let Err(e) = directory_tx.send(cmd).await {
+        eprintln!("{log_prefix}[{id}] Failed to send deregistration request: {e}");
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+
+use crate::{
+    directory::DirectoryRegistration,
+    routing::{RouteSelector, UniformSelector},
+};
+
+// Picks uniformly among the nodes that none of the last `window`
+// routes went through, falling back to every candidate when too few
+// of those are left; this spreads a client's routes over the network
+pub struct AvoidRecentSelector {
+    window: usize,
+    recent: VecDeque<Vec<String>>,
+}
+
+impl AvoidRecentSelector {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn is_recent(&self, id: &str) -> bool {
+        self.recent
+            .iter()
+            .flatten()
+            .any(|recent_id| recent_id == id)
+    }
+}
+
+impl RouteSelector for AvoidRecentSelector {
+    fn select(
+        &mut self,
+        candidates: &[&DirectoryRegistration],
+        count: usize,
+        rng: &mut StdRng,
+    ) -> Vec<DirectoryRegistration> {
+        let fresh = candidates
+            .iter()
+            .copied()
+            .filter(|entry| !self.is_recent(&entry.id))
+            .collect::<Vec<&DirectoryRegistration>>();
+        if fresh.len() >= count {
+            UniformSelector.select(&fresh, count, rng)
+        } else {
+            UniformSelector.select(candidates, count, rng)
+        }
+    }
+
+    fn record(&mut self, route: &[DirectoryRegistration]) {
+        if self.window == 0 {
+            return;
+        }
+        self.recent
+            .push_back(route.iter().map(|entry| entry.id.clone()).collect());
+        while self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use rand::rngs::StdRng;
+
+use crate::{
+    directory::DirectoryRegistration,
+    routing::{sample_weighted, RouteSelector},
+};
+
+// Picks nodes proportionally to their advertised capacity, spreading
+// load the way bandwidth-weighted networks such as Tor do
+pub struct CapacityWeightedSelector;
+
+impl RouteSelector for CapacityWeightedSelector {
+    fn select(
+        &mut self,
+        candidates: &[&DirectoryRegistration],
+        count: usize,
+        rng: &mut StdRng,
+    ) -> Vec<DirectoryRegistration> {
+        sample_weighted(candidates, |entry| entry.capacity, count, rng)
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng};
+use rand_distr::{Distribution, Exp};
+use sphinx_packet::header::delays::Delay;
+
+use crate::config::DelayDistribution;
+
+pub const DEFAULT_AVERAGE_DELAY_MILLIS: u64 = 1000;
+
+// Samples one Sphinx delay per hop from the given distribution, using
+// the mean delay each hop advertises; mirrors
+// sphinx_packet::header::delays::generate_from_average_duration but
+// draws from the given generator instead of the thread-local one
+pub fn generate_delays(
+    distribution: DelayDistribution,
+    means: &[Duration],
+    rng: &mut StdRng,
+) -> Vec<Delay> {
+    means
+        .iter()
+        .map(|mean| Delay::new_from_nanos(sample_nanos(distribution, *mean, rng)))
+        .collect()
+}
+
+fn sample_nanos(distribution: DelayDistribution, mean: Duration, rng: &mut StdRng) -> u64 {
+    let mean_nanos = mean.as_nanos() as f64;
+    match distribution {
+        DelayDistribution::Exponential => match Exp::new(1.0 / mean_nanos) {
+            Ok(exp) if mean_nanos > 0.0 => exp.sample(rng).round() as u64,
+            _ => 0,
+        },
+        DelayDistribution::Fixed => mean_nanos.round() as u64,
+        DelayDistribution::Uniform if mean_nanos > 0.0 => {
+            rng.random_range(0.0..2.0 * mean_nanos).round() as u64
+        }
+        DelayDistribution::Uniform | DelayDistribution::Zero => 0,
+    }
+}
@@ -0,0 +1,26 @@
+use rand::rngs::StdRng;
+
+use crate::{
+    directory::DirectoryRegistration,
+    routing::{sample_weighted, RouteSelector},
+};
+
+// Picks nodes inversely proportionally to their advertised latency,
+// so that fast nodes are favoured without slow ones being excluded
+pub struct LatencyAwareSelector;
+
+impl RouteSelector for LatencyAwareSelector {
+    fn select(
+        &mut self,
+        candidates: &[&DirectoryRegistration],
+        count: usize,
+        rng: &mut StdRng,
+    ) -> Vec<DirectoryRegistration> {
+        sample_weighted(
+            candidates,
+            |entry| 1.0 / (1.0 + entry.latency.as_secs_f64() * 1000.0),
+            count,
+            rng,
+        )
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+
+use crate::directory::{DirectoryRegistration, NodeRole};
+
+// Known mix nodes grouped into the configured number of layers and
+// sorted by id within each layer; a layer none of whose nodes are
+// known yet is left empty
+pub fn mix_layers(
+    address_book: &HashMap<String, DirectoryRegistration>,
+    layers: usize,
+) -> Vec<Vec<&DirectoryRegistration>> {
+    let mut groups: Vec<Vec<&DirectoryRegistration>> = vec![Vec::new(); layers];
+    for entry in address_book.values() {
+        let group = match entry.role {
+            NodeRole::Mix { layer } => groups.get_mut(layer),
+            _ => None,
+        };
+        if let Some(group) = group {
+            group.push(entry);
+        }
+    }
+    for group in groups.iter_mut() {
+        group.sort_by(|a, b| a.id.cmp(&b.id));
+    }
+    groups
+}
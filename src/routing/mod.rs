@@ -0,0 +1,17 @@
+mod avoid_recent_selector;
+mod capacity_weighted_selector;
+mod hop_delays;
+mod latency_aware_selector;
+mod mix_layers;
+mod route_selector;
+mod sphinx_builder;
+mod uniform_selector;
+
+pub use avoid_recent_selector::AvoidRecentSelector;
+pub use capacity_weighted_selector::CapacityWeightedSelector;
+pub use hop_delays::{generate_delays, DEFAULT_AVERAGE_DELAY_MILLIS};
+pub use latency_aware_selector::LatencyAwareSelector;
+pub use mix_layers::mix_layers;
+pub use route_selector::{new_route_selector, sample_weighted, RouteSelector, DEFAULT_PATH_LENGTH};
+pub use sphinx_builder::SphinxBuilder;
+pub use uniform_selector::UniformSelector;
@@ -0,0 +1,68 @@
+use rand::{
+    distr::{weighted::WeightedIndex, Distribution},
+    rngs::StdRng,
+    Rng,
+};
+
+use crate::{
+    config::RouteSelection,
+    directory::DirectoryRegistration,
+    routing::{
+        AvoidRecentSelector, CapacityWeightedSelector, LatencyAwareSelector, UniformSelector,
+    },
+};
+
+// Hops on a free route, not counting the recipient or its provider,
+// unless the network or the message sets otherwise
+pub const DEFAULT_PATH_LENGTH: usize = 3;
+
+// Picks the nodes a client routes its packets through; the topology
+// decides which nodes are candidates for which hop, the selector
+// decides among them
+pub trait RouteSelector: Send {
+    // Picks up to `count` distinct nodes out of `candidates`, which are
+    // sorted by id, in the order the route visits them
+    fn select(
+        &mut self,
+        candidates: &[&DirectoryRegistration],
+        count: usize,
+        rng: &mut StdRng,
+    ) -> Vec<DirectoryRegistration>;
+
+    // Called with the intermediate hops of every route built
+    fn record(&mut self, _route: &[DirectoryRegistration]) {}
+}
+
+pub fn new_route_selector(selection: &RouteSelection) -> Box<dyn RouteSelector> {
+    match selection {
+        RouteSelection::Uniform => Box::new(UniformSelector),
+        RouteSelection::CapacityWeighted => Box::new(CapacityWeightedSelector),
+        RouteSelection::LatencyAware => Box::new(LatencyAwareSelector),
+        RouteSelection::AvoidRecent { window } => Box::new(AvoidRecentSelector::new(*window)),
+    }
+}
+
+// Draws up to `count` distinct candidates one after another, each with
+// probability proportional to its weight among those left; if all
+// remaining weights are zero the rest are drawn uniformly
+pub fn sample_weighted(
+    candidates: &[&DirectoryRegistration],
+    weight: impl Fn(&DirectoryRegistration) -> f64,
+    count: usize,
+    rng: &mut StdRng,
+) -> Vec<DirectoryRegistration> {
+    let mut remaining = candidates
+        .iter()
+        .map(|&entry| (entry, weight(entry).max(0.0)))
+        .collect::<Vec<(&DirectoryRegistration, f64)>>();
+    let mut route = vec![];
+    while route.len() < count && !remaining.is_empty() {
+        let index = match WeightedIndex::new(remaining.iter().map(|(_, weight)| *weight)) {
+            Ok(weighted) => weighted.sample(rng),
+            Err(_) => rng.random_range(0..remaining.len()),
+        };
+        let (entry, _) = remaining.remove(index);
+        route.push(entry.clone());
+    }
+    route
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng};
+use sphinx_packet::{
+    header::delays::Delay,
+    packet::builder::{SphinxPacketBuilder, DEFAULT_PAYLOAD_SIZE},
+    payload::PAYLOAD_OVERHEAD_SIZE,
+    route::{Destination, DestinationAddressBytes, Node, NodeAddressBytes},
+    surb::{SURBMaterial, SURB},
+    Error as SphinxError,
+};
+use x25519_dalek::StaticSecret;
+
+use crate::{
+    bytes::{bytes_to_string_truncate_zeroes, str_to_byte_array_32},
+    config::DelayDistribution,
+    directory::DirectoryRegistration,
+    packet::{Message, Packet},
+    routing::generate_delays,
+};
+
+// Wraps messages in Sphinx packets on behalf of a node, drawing hop
+// delays, initial secrets and packet ids from the node's generators
+pub struct SphinxBuilder {
+    id: String,
+    delay_distribution: DelayDistribution,
+    key_rng: StdRng,
+    delay_rng: StdRng,
+    packet_id_rng: StdRng,
+}
+
+impl SphinxBuilder {
+    pub fn new(
+        id: &str,
+        delay_distribution: DelayDistribution,
+        key_rng: StdRng,
+        delay_rng: StdRng,
+        packet_id_rng: StdRng,
+    ) -> Self {
+        Self {
+            id: id.to_owned(),
+            delay_distribution,
+            key_rng,
+            delay_rng,
+            packet_id_rng,
+        }
+    }
+
+    // Whether `message` fits in the payload of a single packet
+    pub fn fits(&self, message: &Message) -> bool {
+        serde_yaml::to_string(message).unwrap().len()
+            <= DEFAULT_PAYLOAD_SIZE - PAYLOAD_OVERHEAD_SIZE
+    }
+
+    // Builds a packet carrying `message` to `recipient` through
+    // `route`, addressed to the first hop of the route
+    pub fn build(
+        &mut self,
+        route: &[DirectoryRegistration],
+        recipient: &DirectoryRegistration,
+        message: &Message,
+    ) -> Result<Packet, SphinxError> {
+        self.build_with_identifier(route, recipient, message, [0u8; 16])
+    }
+
+    // As `build`, also handing the recipient the given identifier in
+    // the packet's final hop
+    pub fn build_with_identifier(
+        &mut self,
+        route: &[DirectoryRegistration],
+        recipient: &DirectoryRegistration,
+        message: &Message,
+        identifier: [u8; 16],
+    ) -> Result<Packet, SphinxError> {
+        let (forward_route, destination, delays) =
+            self.header_material(route, recipient, identifier);
+        let message_yaml = serde_yaml::to_string(message).unwrap();
+        let initial_secret = StaticSecret::from(self.key_rng.random::<[u8; 32]>());
+        let sphinx_packet = SphinxPacketBuilder::new()
+            .with_initial_secret(&initial_secret)
+            .build_packet(
+                message_yaml.as_bytes(),
+                &forward_route,
+                &destination,
+                &delays,
+            )?;
+        let first_hop_id = route.first().map_or(&recipient.id, |entry| &entry.id);
+        Ok(Packet::new(
+            first_hop_id,
+            &self.id,
+            sphinx_packet,
+            &mut self.packet_id_rng,
+        )
+        .with_kind(message.kind))
+    }
+
+    // Builds a SURB leading through `route` to `recipient`, which
+    // receives the replies sent through it under the given identifier
+    pub fn build_surb(
+        &mut self,
+        route: &[DirectoryRegistration],
+        recipient: &DirectoryRegistration,
+        identifier: [u8; 16],
+    ) -> Result<SURB, SphinxError> {
+        let (surb_route, destination, delays) = self.header_material(route, recipient, identifier);
+        let initial_secret = StaticSecret::from(self.key_rng.random::<[u8; 32]>());
+        SURB::new(
+            initial_secret,
+            SURBMaterial::new(surb_route, delays, destination),
+        )
+    }
+
+    // Builds a packet carrying `message` through a SURB, addressed to
+    // the first hop the SURB leads through
+    pub fn build_reply(&mut self, surb: SURB, message: &Message) -> Result<Packet, SphinxError> {
+        let message_yaml = serde_yaml::to_string(message).unwrap();
+        let (sphinx_packet, first_hop) =
+            surb.use_surb(message_yaml.as_bytes(), DEFAULT_PAYLOAD_SIZE)?;
+        Ok(Packet::new(
+            &bytes_to_string_truncate_zeroes(first_hop.as_bytes()),
+            &self.id,
+            sphinx_packet,
+            &mut self.packet_id_rng,
+        )
+        .with_kind(message.kind))
+    }
+
+    // The hops, destination and per-hop delays of a Sphinx header that
+    // leads through `route` to `recipient`
+    fn header_material(
+        &mut self,
+        route: &[DirectoryRegistration],
+        recipient: &DirectoryRegistration,
+        identifier: [u8; 16],
+    ) -> (Vec<Node>, Destination, Vec<Delay>) {
+        let nodes = route
+            .iter()
+            .chain([recipient])
+            .map(|entry| {
+                Node::new(
+                    NodeAddressBytes::from_bytes(str_to_byte_array_32(&entry.id)),
+                    entry.pk,
+                )
+            })
+            .collect::<Vec<Node>>();
+        let destination = Destination::new(
+            DestinationAddressBytes::from_bytes(str_to_byte_array_32(&recipient.id)),
+            identifier,
+        );
+        // Every hop's delay is drawn around the mean it advertises, the
+        // recipient's included
+        let delay_means = route
+            .iter()
+            .chain([recipient])
+            .map(|entry| entry.average_delay)
+            .collect::<Vec<Duration>>();
+        let delays = generate_delays(self.delay_distribution, &delay_means, &mut self.delay_rng);
+        (nodes, destination, delays)
+    }
+}
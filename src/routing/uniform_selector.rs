@@ -0,0 +1,22 @@
+use rand::{rngs::StdRng, seq::IteratorRandom, seq::SliceRandom};
+
+use crate::{directory::DirectoryRegistration, routing::RouteSelector};
+
+// Picks every candidate with equal probability
+pub struct UniformSelector;
+
+impl RouteSelector for UniformSelector {
+    fn select(
+        &mut self,
+        candidates: &[&DirectoryRegistration],
+        count: usize,
+        rng: &mut StdRng,
+    ) -> Vec<DirectoryRegistration> {
+        let mut route = candidates
+            .iter()
+            .map(|&entry| entry.clone())
+            .choose_multiple(rng, count);
+        route.shuffle(rng);
+        route
+    }
+}
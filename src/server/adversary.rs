@@ -0,0 +1,55 @@
+use rand::{rngs::StdRng, Rng};
+use sphinx_packet::SphinxPacket;
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    config,
+    mix::DelayQueue,
+    packet::{Packet, PacketKind},
+};
+
+// Captures packets as the server passes them between nodes and holds
+// copies to replay to the same node later, to check that nodes refuse
+// packets they have processed before
+pub struct Adversary {
+    replay_probability: f64,
+    replay_delay: Duration,
+    replays: DelayQueue,
+    rng: StdRng,
+}
+
+impl Adversary {
+    pub fn new(adversary: &config::Adversary, rng: StdRng) -> Self {
+        Self {
+            replay_probability: adversary
+                .replay_probability
+                .unwrap_or_default()
+                .clamp(0.0, 1.0),
+            replay_delay: Duration::from_millis(adversary.replay_delay_millis.unwrap_or_default()),
+            replays: DelayQueue::default(),
+            rng,
+        }
+    }
+
+    // Keeps a copy of the packet to replay, by chance; copies are
+    // never replayed themselves
+    pub fn capture(&mut self, packet: &Packet, now: Instant) {
+        if packet.kind() == PacketKind::Replay || !self.rng.random_bool(self.replay_probability) {
+            return;
+        }
+        let Ok(body) = SphinxPacket::from_bytes(&packet.body().to_bytes()) else {
+            return;
+        };
+        let copy = Packet::new_with_id(packet.id(), packet.to(), packet.from(), body)
+            .with_kind(PacketKind::Replay);
+        self.replays.push(now + self.replay_delay, copy);
+    }
+
+    pub fn next_release(&self) -> Option<Instant> {
+        self.replays.next_release()
+    }
+
+    pub fn take_due(&mut self, now: Instant) -> Vec<Packet> {
+        self.replays.pop_due(now)
+    }
+}
@@ -1,8 +1,12 @@
+mod adversary;
+mod node_tx;
 mod server;
 mod server_command;
 mod server_registration;
 mod server_registration_error;
 
+pub use adversary::Adversary;
+pub use node_tx::NodeTx;
 pub use server::Server;
 pub use server_command::ServerCommand;
 pub use server_registration::ServerRegistration;
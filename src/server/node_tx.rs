@@ -0,0 +1,40 @@
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::{
+    client::ClientCommand, mix::MixNodeCommand, packet::Packet, provider::ProviderCommand,
+    queue::QueueSender,
+};
+
+// Channel into whichever kind of node is registered at an id
+#[derive(Clone)]
+pub enum NodeTx {
+    Client(QueueSender<ClientCommand>),
+    MixNode(QueueSender<MixNodeCommand>),
+    Provider(QueueSender<ProviderCommand>),
+}
+
+impl NodeTx {
+    // Hands the node a packet without waiting for room in its queue,
+    // so that the server never waits on a node that may in turn be
+    // waiting on the server
+    pub fn send_packet(&self, packet: Packet) -> Result<(), TrySendError<()>> {
+        match self {
+            NodeTx::Client(tx) => tx
+                .try_send(ClientCommand::ReceivePacket(packet))
+                .map_err(erase),
+            NodeTx::MixNode(tx) => tx
+                .try_send(MixNodeCommand::ReceivePacket(packet))
+                .map_err(erase),
+            NodeTx::Provider(tx) => tx
+                .try_send(ProviderCommand::ReceivePacket(packet))
+                .map_err(erase),
+        }
+    }
+}
+
+fn erase<T>(e: TrySendError<T>) -> TrySendError<()> {
+    match e {
+        TrySendError::Full(_) => TrySendError::Full(()),
+        TrySendError::Closed(_) => TrySendError::Closed(()),
+    }
+}
@@ -1,29 +1,61 @@
 use std::collections::{hash_map::Entry, HashMap};
 
-use tokio::sync::mpsc::{self, Receiver as MpscReceiver, Sender as MpscSender};
+use prometheus_client::metrics::{counter::Counter, family::Family};
+use tokio::{
+    sync::mpsc::error::TrySendError,
+    time::{sleep_until, Instant},
+};
 
 use crate::{
-    client::ClientCommand,
     packet::Packet,
-    server::{ServerCommand, ServerRegistration, ServerRegistrationError},
+    prometheus::{MetricFamilies, ReplayLabels, ReplayStatus},
+    provider::ProviderCommand,
+    queue::{self, Overflow, QueueReceiver, QueueSender},
+    server::{Adversary, NodeTx, ServerCommand, ServerRegistration, ServerRegistrationError},
+    stats::Stats,
 };
 
 pub struct Server {
-    server_tx: MpscSender<ServerCommand>,
-    server_rx: MpscReceiver<ServerCommand>,
+    server_tx: QueueSender<ServerCommand>,
+    server_rx: QueueReceiver<ServerCommand>,
     registrations: HashMap<String, ServerRegistration>,
+    adversary: Option<Adversary>,
+    replays: Option<Family<ReplayLabels, Counter>>,
+    stats: Stats,
 }
 
 impl Server {
-    pub fn new(buffer_size: usize) -> Self {
-        let (server_tx, server_rx) = mpsc::channel::<ServerCommand>(buffer_size);
+    pub fn new(buffer_size: usize, stats: Stats) -> Self {
+        let (server_tx, server_rx) = queue::channel::<ServerCommand>(buffer_size);
         Self {
             server_tx,
             server_rx,
             registrations: HashMap::new(),
+            adversary: None,
+            replays: None,
+            stats,
         }
     }
 
+    // Has an adversary replay some of the packets passing through
+    pub fn with_adversary(mut self, adversary: Adversary) -> Self {
+        self.adversary = Some(adversary);
+        self
+    }
+
+    // Has the server drop packets when its queue is full, according to
+    // the overflow policy, rather than block the nodes sending to it
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.server_rx.set_overflow(overflow);
+        self
+    }
+
+    // Has the server report the replays it injects to Prometheus
+    pub fn with_metrics(mut self, mf: &MetricFamilies) -> Self {
+        self.replays = Some(mf.replays.clone());
+        self
+    }
+
     pub async fn register(
         &mut self,
         registration: ServerRegistration,
@@ -35,34 +67,89 @@ impl Server {
     }
 
     pub async fn send(&self, packet: Packet) {
+        let kind = packet.kind();
         match self.registrations.get(packet.to()) {
             Some(registration) => match registration.tx {
                 Some(ref tx) => {
-                    if let Err(e) = tx.send(ClientCommand::ReceivePacket(packet)).await {
-                        eprintln!("[SERVER] Could not forward packet: {e}");
+                    let packet_id = packet.id().to_owned();
+                    let to = packet.to().to_owned();
+                    match tx.send_packet(packet) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(())) => {
+                            println!(
+                                "[SERVER] Queue of \"{to}\" is full, dropping packet \"{packet_id}\""
+                            );
+                            self.stats.record_dropped(kind);
+                        }
+                        Err(e) => {
+                            eprintln!("[SERVER] Could not forward packet: {e}");
+                            self.stats.record_dropped(kind);
+                        }
                     }
                 }
                 None => {
-                    eprintln!("[SERVER] Could not forward packet: client is unavailable");
+                    eprintln!("[SERVER] Could not forward packet: node is unavailable");
+                    self.stats.record_dropped(kind);
                 }
             },
             None => {
-                println!("[SERVER] No client registered at id \"{}\"", packet.to());
+                println!(
+                    "[SERVER] No node registered at id \"{}\", dropping packet \"{}\" from \"{}\" ({} bytes)",
+                    packet.to(),
+                    packet.id(),
+                    packet.from(),
+                    packet.body().len(),
+                );
+                self.stats.record_dropped(kind);
+            }
+        }
+    }
+
+    pub async fn fetch_mailbox(&self, provider: &str, id: String) {
+        match self.registrations.get(provider) {
+            Some(ServerRegistration {
+                tx: Some(NodeTx::Provider(tx)),
+                ..
+            }) => {
+                // The client fetches again at its next interval anyway
+                if let Err(e) = tx.try_send(ProviderCommand::FetchMailbox(id)) {
+                    eprintln!(
+                        "[SERVER] Could not pass on mailbox fetch to provider \"{provider}\": {e}"
+                    );
+                }
+            }
+            _ => {
+                eprintln!(
+                    "[SERVER] No provider registered at id \"{provider}\", ignoring mailbox fetch for \"{id}\""
+                );
             }
         }
     }
 
     pub async fn listen(&mut self) {
         println!("[SERVER] Starting listening");
-        while let Some(cmd) = self.server_rx.recv().await {
+        loop {
+            let next_replay = self.adversary.as_ref().and_then(Adversary::next_release);
+            let cmd = tokio::select! {
+                cmd = self.server_rx.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => return,
+                },
+                _ = async {
+                    match next_replay {
+                        Some(next_replay) => sleep_until(next_replay).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.replay().await;
+                    continue;
+                }
+            };
             match cmd {
                 ServerCommand::Register(registration, response_tx) => {
                     match self.register(registration).await {
                         Ok(registration) => {
-                            println!(
-                                "[SERVER] Client with id \"{}\" registered",
-                                &registration.id
-                            );
+                            println!("[SERVER] Node with id \"{}\" registered", &registration.id);
                             if let Err(e) = response_tx.send(Ok(())).await {
                                 eprintln!("[SERVER] Failed to notify client of successful registration: {e}");
                             }
@@ -75,12 +162,43 @@ impl Server {
                         }
                     }
                 }
-                ServerCommand::Send(packet) => self.send(packet).await,
+                ServerCommand::Send(packet) => {
+                    if let Some(adversary) = self.adversary.as_mut() {
+                        adversary.capture(&packet, Instant::now());
+                    }
+                    self.send(packet).await
+                }
+                ServerCommand::FetchMailbox(provider, id) => {
+                    self.fetch_mailbox(&provider, id).await
+                }
+            }
+        }
+    }
+
+    // Sends on every replay the adversary holds that is due
+    async fn replay(&mut self) {
+        let Some(adversary) = self.adversary.as_mut() else {
+            return;
+        };
+        for packet in adversary.take_due(Instant::now()) {
+            println!(
+                "[SERVER] Adversary replaying packet \"{}\" to \"{}\"",
+                packet.id(),
+                packet.to()
+            );
+            if let Some(replays) = &self.replays {
+                replays
+                    .get_or_create(&ReplayLabels {
+                        node: packet.to().to_owned(),
+                        status: ReplayStatus::Injected,
+                    })
+                    .inc();
             }
+            self.send(packet).await;
         }
     }
 
-    pub fn get_tx(&self) -> MpscSender<ServerCommand> {
+    pub fn get_tx(&self) -> QueueSender<ServerCommand> {
         self.server_tx.clone()
     }
 }
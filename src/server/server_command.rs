@@ -1,6 +1,8 @@
 use tokio::sync::mpsc::Sender as MpscSender;
 
-use crate::{packet::Packet, server::ServerRegistration, server::ServerRegistrationError};
+use crate::{
+    packet::Packet, queue::Droppable, server::ServerRegistration, server::ServerRegistrationError,
+};
 
 pub enum ServerCommand {
     Register(
@@ -8,4 +10,16 @@ pub enum ServerCommand {
         MpscSender<Result<(), ServerRegistrationError>>,
     ),
     Send(Packet),
+    // Asks the provider with the first id to hand over the mailbox of
+    // the client with the second id
+    FetchMailbox(String, String),
+}
+
+impl Droppable for ServerCommand {
+    fn packet(&self) -> Option<&Packet> {
+        match self {
+            ServerCommand::Send(packet) => Some(packet),
+            _ => None,
+        }
+    }
 }
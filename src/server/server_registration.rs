@@ -1,8 +1,6 @@
-use tokio::sync::mpsc::Sender as MpscSender;
-
-use crate::client::ClientCommand;
+use crate::server::NodeTx;
 
 pub struct ServerRegistration {
     pub id: String,
-    pub tx: Option<MpscSender<ClientCommand>>,
+    pub tx: Option<NodeTx>,
 }
@@ -0,0 +1,7 @@
+mod simulation;
+mod simulation_builder;
+mod simulation_error;
+
+pub use simulation::Simulation;
+pub use simulation_builder::SimulationBuilder;
+pub use simulation_error::SimulationError;
@@ -0,0 +1,761 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use sphinx_packet::constants::MAX_PATH_LENGTH;
+use tokio::{
+    runtime::Builder as RuntimeBuilder,
+    signal,
+    sync::mpsc,
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+
+use crate::{
+    churn::Churn,
+    client::{
+        AckTracker, Client, ClientCommand, CoverTraffic, DeliveryReport, Fragmenter, Mailbox,
+        ReceivedMessage, ReplyBlocks, SendQueue, MAX_SHARDS,
+    },
+    config::{self, ClockMode, Config, OverflowPolicy, Topology},
+    directory::Directory,
+    mix::{MixNode, MixNodeCommand, MixNodeSettings},
+    prometheus::{self, MetricFamilies},
+    provider::{Provider, ProviderCommand},
+    queue::{Droppable, Overflow, QueueSender},
+    rng::{RngStream, Rngs},
+    routing::{DEFAULT_AVERAGE_DELAY_MILLIS, DEFAULT_PATH_LENGTH},
+    server::{Adversary, Server},
+    simulation::{SimulationBuilder, SimulationError},
+    stats::{Stats, Summary},
+    traffic::{load_trace, schedule_trace, SendSchedule, SocialGraph, TraceSend},
+    user::{User, Workload},
+};
+
+const DEFAULT_METRICS_ADDRESS: &str = "0.0.0.0:5050";
+const DEFAULT_SERVER_BUFFER_SIZE: usize = 32;
+const DEFAULT_DIRECTORY_BUFFER_SIZE: usize = 32;
+const DEFAULT_CLIENT_BUFFER_SIZE: usize = 32;
+const DEFAULT_MIX_NODE_BUFFER_SIZE: usize = 32;
+const DEFAULT_MIX_NODE_CAPACITY: f64 = 1.0;
+const DEFAULT_PROVIDER_BUFFER_SIZE: usize = 32;
+const DEFAULT_FETCH_INTERVAL_MILLIS: u64 = 10000;
+const DEFAULT_DRAIN_MILLIS: u64 = 10000;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A single run of the mixnet: a server, a directory, the configured
+// mix nodes, and a client and user per configured client
+pub struct Simulation {
+    pub(crate) config: Config,
+    pub(crate) handle_ctrl_c: bool,
+}
+
+impl Simulation {
+    pub fn builder() -> SimulationBuilder {
+        SimulationBuilder::default()
+    }
+
+    // Runs the simulation on a runtime of its own, which is required
+    // for a simulated clock, blocking until it completes
+    pub fn run_blocking(self) -> Result<Summary, SimulationError> {
+        // A simulated clock requires a single-threaded runtime whose
+        // time starts paused, so that it auto-advances to the next
+        // timer as soon as all tasks are idle
+        let clock_mode = self
+            .config
+            .clock
+            .as_ref()
+            .and_then(|clock| clock.mode)
+            .unwrap_or_default();
+        let runtime = match clock_mode {
+            ClockMode::Wall => RuntimeBuilder::new_multi_thread().enable_all().build(),
+            ClockMode::Simulated => RuntimeBuilder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build(),
+        }
+        .map_err(SimulationError::Runtime)?;
+        runtime.block_on(self.run())
+    }
+
+    // Runs the simulation on the current runtime; the clock mode is
+    // whatever that runtime provides
+    pub async fn run(self) -> Result<Summary, SimulationError> {
+        let config = self.config;
+        let duration = config
+            .run
+            .as_ref()
+            .and_then(|run| run.duration_millis)
+            .map(Duration::from_millis);
+        let max_messages = config.run.as_ref().and_then(|run| run.max_messages);
+        let drain = Duration::from_millis(
+            config
+                .run
+                .as_ref()
+                .and_then(|run| run.drain_millis)
+                .unwrap_or(DEFAULT_DRAIN_MILLIS),
+        );
+        let rngs = Rngs::new(config.seed);
+        let network = config.network.clone().unwrap_or_default();
+        let topology = network.topology.unwrap_or_default();
+        let layers = config
+            .mix_nodes
+            .as_ref()
+            .map(|mix_nodes| mix_nodes.layers)
+            .unwrap_or_default();
+        if topology != Topology::FreeRoute && layers == 0 {
+            return Err(SimulationError::Config(
+                "stratified and cascade topologies need at least one layer of mix nodes".to_owned(),
+            ));
+        }
+        // Routes also take a hop through the recipient's provider, if
+        // there are providers
+        let provider_count = config
+            .providers
+            .as_ref()
+            .map(|providers| providers.count)
+            .unwrap_or_default();
+        let provider_hops = usize::from(provider_count > 0);
+        if layers + provider_hops >= MAX_PATH_LENGTH {
+            return Err(SimulationError::Config(format!(
+                "at most {} layers of mix nodes fit in a Sphinx header{}",
+                MAX_PATH_LENGTH - 1 - provider_hops,
+                if provider_hops > 0 {
+                    " along with providers"
+                } else {
+                    ""
+                }
+            )));
+        }
+        let erasure_codings = config.erasure_coding.iter().chain(
+            config
+                .clients
+                .iter()
+                .flatten()
+                .filter_map(|client| client.erasure_coding.as_ref()),
+        );
+        for coding in erasure_codings {
+            if coding.needed == 0 || coding.fragments < coding.needed {
+                return Err(SimulationError::Config(format!(
+                    "erasure coding needs 1 <= needed <= fragments, got {} and {}",
+                    coding.needed, coding.fragments
+                )));
+            }
+            if coding.fragments > MAX_SHARDS {
+                return Err(SimulationError::Config(format!(
+                    "erasure coding supports at most {MAX_SHARDS} fragments"
+                )));
+            }
+        }
+        let traffic_profiles = config.traffic_profiles.unwrap_or_default();
+        let mut client_configs = config.clients.unwrap_or_default();
+
+        // Load the message trace, if any, creating clients for any ids
+        // that only appear in the trace
+        let mut trace_sends = HashMap::<String, Vec<TraceSend>>::new();
+        let mut trace_path_lengths = vec![];
+        if let Some(trace_config) = &config.trace {
+            let events = load_trace(trace_config)
+                .map_err(|e| SimulationError::Trace(trace_config.path.clone(), e))?;
+            for event in &events {
+                if let Some(path_length) = event.path_length {
+                    trace_path_lengths.push((
+                        format!(
+                            "trace message from \"{}\" at {}s",
+                            event.sender, event.timestamp
+                        ),
+                        path_length,
+                    ));
+                }
+                for id in [&event.sender, &event.recipient] {
+                    if !client_configs
+                        .iter()
+                        .any(|client_config| &client_config.id == id)
+                    {
+                        println!("Creating client \"{id}\" found in trace");
+                        client_configs.push(config::Client {
+                            id: id.clone(),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+            let speed = trace_config.speed.unwrap_or(1.0);
+            if !speed.is_finite() || speed <= 0.0 {
+                return Err(SimulationError::Config(format!(
+                    "trace speed must be a positive number, got {speed}"
+                )));
+            }
+            for (sender, trace_send) in schedule_trace(&events, speed) {
+                trace_sends.entry(sender).or_default().push(trace_send);
+            }
+        }
+
+        // Users wait a positive interval between sends on average, or
+        // they would send forever without time ever passing
+        let traffics = traffic_profiles.values().chain(
+            client_configs
+                .iter()
+                .filter_map(|client_config| client_config.traffic.as_ref()),
+        );
+        for traffic in traffics {
+            if let Some(rate) = traffic
+                .rate_per_second
+                .filter(|rate| !rate.is_finite() || *rate <= 0.0)
+            {
+                return Err(SimulationError::Config(format!(
+                    "traffic rate must be a positive number, got {rate}"
+                )));
+            }
+            if let Err(e) = SendSchedule::for_traffic(traffic) {
+                return Err(SimulationError::Config(e.to_string()));
+            }
+        }
+
+        // Free routes need at least one hop, few enough to fit in a
+        // Sphinx header along with the recipient's provider and the
+        // recipient, and no more than there are nodes to route through,
+        // or clients would wait forever for enough nodes to show up;
+        // layered routes take one hop per layer whatever the length
+        if topology == Topology::FreeRoute {
+            let max_path_length = match config.mix_nodes.as_ref() {
+                Some(mix_nodes) => mix_nodes.layers * mix_nodes.nodes_per_layer,
+                None => client_configs.len().saturating_sub(2),
+            }
+            .min(MAX_PATH_LENGTH - 1 - provider_hops);
+            let path_lengths = [(
+                "network".to_owned(),
+                network.path_length.unwrap_or(DEFAULT_PATH_LENGTH),
+            )]
+            .into_iter()
+            .chain(traffic_profiles.iter().filter_map(|(name, traffic)| {
+                traffic
+                    .path_length
+                    .map(|path_length| (format!("traffic profile \"{name}\""), path_length))
+            }))
+            .chain(client_configs.iter().filter_map(|client_config| {
+                client_config
+                    .traffic
+                    .as_ref()
+                    .and_then(|traffic| traffic.path_length)
+                    .map(|path_length| {
+                        (
+                            format!("traffic of client \"{}\"", client_config.id),
+                            path_length,
+                        )
+                    })
+            }))
+            .chain(trace_path_lengths);
+            for (source, path_length) in path_lengths {
+                if path_length == 0 || path_length > max_path_length {
+                    return Err(SimulationError::Config(format!(
+                        "path length of {source} must be between 1 and {max_path_length}, got {path_length}"
+                    )));
+                }
+            }
+        }
+        let stats = Stats::new(max_messages);
+
+        // Turn on metrics if enabled; they are served until the run
+        // returns
+        let (mf, _metrics_server) = match config
+            .metrics
+            .filter(|metrics_config| metrics_config.enable.unwrap_or_default())
+        {
+            Some(metrics_config) => {
+                let address = metrics_config
+                    .address
+                    .unwrap_or_else(|| DEFAULT_METRICS_ADDRESS.to_owned());
+                let (mf, metrics_server) = prometheus::setup(&address)
+                    .map_err(|e| SimulationError::Metrics(address, e))?;
+                (Some(mf), Some(metrics_server))
+            }
+            None => (None, None),
+        };
+
+        // Create server
+        let server_buffer_size = if let Some(server) = &config.server {
+            server.buffer_size.unwrap_or(DEFAULT_SERVER_BUFFER_SIZE)
+        } else {
+            DEFAULT_SERVER_BUFFER_SIZE
+        };
+        let mut s = Server::new(server_buffer_size, stats.clone());
+        if let Some(overflow) = overflow(
+            "server",
+            "[SERVER]",
+            config.server.as_ref().and_then(|server| server.overflow),
+            &rngs,
+            &stats,
+            &mf,
+        ) {
+            s = s.with_overflow(overflow);
+        }
+        if let Some(adversary) = &config.adversary {
+            s = s.with_adversary(Adversary::new(
+                adversary,
+                rngs.stream(RngStream::Adversary, "server"),
+            ));
+        }
+        if let Some(mf) = &mf {
+            s = s.with_metrics(mf);
+        }
+        let server_tx = s.get_tx();
+        let server = tokio::spawn(async move { s.listen().await });
+
+        // Create directory
+        let directory_buffer_size = if let Some(directory) = config.directory {
+            directory
+                .buffer_size
+                .unwrap_or(DEFAULT_DIRECTORY_BUFFER_SIZE)
+        } else {
+            DEFAULT_DIRECTORY_BUFFER_SIZE
+        };
+        let mut d = Directory::new(directory_buffer_size);
+        let directory_tx = d.get_tx();
+        let directory = tokio::spawn(async move { d.listen().await });
+
+        // Create mix nodes
+        let mut mix_node_set = JoinSet::new();
+        let mut mix_node_txs = vec![];
+        if let Some(mix_nodes) = &config.mix_nodes {
+            let buffer_size = mix_nodes
+                .buffer_size
+                .unwrap_or(DEFAULT_MIX_NODE_BUFFER_SIZE);
+            for layer in 0..mix_nodes.layers {
+                for index in 0..mix_nodes.nodes_per_layer {
+                    let id = format!("mix-{layer}-{index}");
+                    let overrides = mix_nodes.nodes.iter().flatten().find(|node| node.id == id);
+                    let settings = MixNodeSettings {
+                        layer,
+                        layers: mix_nodes.layers,
+                        capacity: overrides
+                            .and_then(|node| node.capacity)
+                            .or(mix_nodes.capacity)
+                            .unwrap_or(DEFAULT_MIX_NODE_CAPACITY),
+                        latency: Duration::from_millis(
+                            overrides
+                                .and_then(|node| node.latency_millis)
+                                .or(mix_nodes.latency_millis)
+                                .unwrap_or_default(),
+                        ),
+                        average_delay: Duration::from_millis(
+                            overrides
+                                .and_then(|node| node.average_delay_millis)
+                                .or(mix_nodes.average_delay_millis)
+                                .or(network.average_delay_millis)
+                                .unwrap_or(DEFAULT_AVERAGE_DELAY_MILLIS),
+                        ),
+                        mixing: overrides
+                            .and_then(|node| node.mixing)
+                            .or(mix_nodes.mixing)
+                            .or(network.mixing)
+                            .unwrap_or_default(),
+                        loops: mix_nodes.loops.clone(),
+                        availability: overrides
+                            .and_then(|node| node.availability.as_ref())
+                            .or(mix_nodes.availability.as_ref())
+                            .or(network.availability.as_ref())
+                            .cloned(),
+                    };
+                    let mut mix_node = MixNode::new(
+                        &id,
+                        settings,
+                        directory_tx.clone(),
+                        buffer_size,
+                        &rngs,
+                        stats.clone(),
+                        &network,
+                    );
+                    if let Some(overflow) =
+                        overflow(&id, "[MIX]", mix_nodes.overflow, &rngs, &stats, &mf)
+                    {
+                        mix_node = mix_node.with_overflow(overflow);
+                    }
+                    if let Some(mf) = &mf {
+                        mix_node = mix_node.with_metrics(mf);
+                    }
+                    mix_node_txs.push(mix_node.get_tx());
+                    let server_tx = server_tx.clone();
+                    mix_node_set.spawn(async move { mix_node.listen(server_tx).await });
+                }
+            }
+        }
+
+        // Create providers
+        let mut provider_set = JoinSet::new();
+        let mut provider_txs = vec![];
+        let mut provider_ids = vec![];
+        if let Some(providers) = &config.providers {
+            let buffer_size = providers
+                .buffer_size
+                .unwrap_or(DEFAULT_PROVIDER_BUFFER_SIZE);
+            for index in 0..providers.count {
+                let id = format!("provider-{index}");
+                let mut provider = Provider::new(
+                    &id,
+                    directory_tx.clone(),
+                    buffer_size,
+                    &rngs,
+                    stats.clone(),
+                    &network,
+                );
+                if let Some(overflow) =
+                    overflow(&id, "[PROVIDER]", providers.overflow, &rngs, &stats, &mf)
+                {
+                    provider = provider.with_overflow(overflow);
+                }
+                if let Some(mf) = &mf {
+                    provider = provider.with_metrics(mf);
+                }
+                provider_txs.push(provider.get_tx());
+                provider_ids.push(id);
+                let server_tx = server_tx.clone();
+                provider_set.spawn(async move { provider.listen(server_tx).await });
+            }
+        }
+        let default_fetch_interval_millis = config
+            .providers
+            .as_ref()
+            .and_then(|providers| providers.fetch_interval_millis)
+            .unwrap_or(DEFAULT_FETCH_INTERVAL_MILLIS);
+
+        // Create clients
+        let mut client_set = JoinSet::new();
+        let mut user_set = JoinSet::new();
+        let mut client_txs = vec![];
+        let all_ids = Arc::new(
+            client_configs
+                .iter()
+                .map(|client_config| client_config.id.clone())
+                .collect::<Vec<String>>(),
+        );
+        let graph = Arc::new(config.social_graph.as_ref().map(|graph_config| {
+            SocialGraph::new(
+                graph_config,
+                &all_ids,
+                &mut rngs.stream(RngStream::SocialGraph, ""),
+            )
+        }));
+        for (index, client_config) in client_configs.into_iter().enumerate() {
+            let buffer_size = client_config
+                .buffer_size
+                .unwrap_or(DEFAULT_CLIENT_BUFFER_SIZE);
+            let directory_tx = directory_tx.clone();
+            let mut client = Client::new(
+                &client_config.id,
+                directory_tx,
+                buffer_size,
+                &mf,
+                &rngs,
+                stats.clone(),
+                &network,
+            )
+            .with_layers(layers);
+            if let Some(overflow) = overflow(
+                &client_config.id,
+                "[CLIENT]",
+                client_config.overflow,
+                &rngs,
+                &stats,
+                &mf,
+            ) {
+                client = client.with_overflow(overflow);
+            }
+            // With providers, every client gets a mailbox, at the
+            // configured provider or otherwise at the next one in turn
+            if !provider_ids.is_empty() {
+                let provider = client_config
+                    .provider
+                    .clone()
+                    .unwrap_or_else(|| provider_ids[index % provider_ids.len()].clone());
+                let fetch_interval_millis = client_config
+                    .fetch_interval_millis
+                    .unwrap_or(default_fetch_interval_millis)
+                    .max(1);
+                client = client.with_mailbox(Mailbox {
+                    provider,
+                    fetch_interval: Duration::from_millis(fetch_interval_millis),
+                });
+            }
+            if let Some(cover) = client_config.cover.as_ref().or(config.cover.as_ref()) {
+                client = client.with_cover(CoverTraffic::new(
+                    cover,
+                    rngs.stream(RngStream::Cover, &client_config.id),
+                ));
+                if let Some(send_queue) = cover.send_rate_per_second.and_then(|rate| {
+                    SendQueue::new(rate, rngs.stream(RngStream::SendSlots, &client_config.id))
+                }) {
+                    client = client.with_send_queue(send_queue);
+                }
+            }
+            if let Some(availability) = client_config
+                .availability
+                .as_ref()
+                .or(network.availability.as_ref())
+            {
+                client = client.with_churn(Churn::new(
+                    availability,
+                    rngs.stream(RngStream::Churn, &client_config.id),
+                ));
+            }
+            let mut delivery_rx = None;
+            if let Some(acks) = &config.acks {
+                let (delivery_tx, rx) = mpsc::channel::<DeliveryReport>(buffer_size);
+                client = client.with_acks(
+                    AckTracker::new(acks, rngs.stream(RngStream::MessageIds, &client_config.id)),
+                    delivery_tx,
+                );
+                delivery_rx = Some(rx);
+            }
+            let mut inbox = None;
+            if let Some(surbs) = &config.surbs {
+                let (inbox_tx, inbox_rx) = mpsc::channel::<ReceivedMessage>(buffer_size);
+                client = client.with_surbs(
+                    ReplyBlocks::new(surbs, rngs.stream(RngStream::Surbs, &client_config.id)),
+                    inbox_tx,
+                );
+                inbox = Some((inbox_rx, surbs.auto_reply.unwrap_or_default()));
+            }
+            // Messages too large for a single packet are always split,
+            // the fragmentation section only tuning how
+            client = client.with_fragmentation(Fragmenter::new(
+                &config.fragmentation.clone().unwrap_or_default(),
+                client_config
+                    .erasure_coding
+                    .clone()
+                    .or_else(|| config.erasure_coding.clone()),
+                rngs.stream(RngStream::Fragments, &client_config.id),
+            ));
+            let client_tx = client.get_tx();
+            client_txs.push(client_tx.clone());
+            let server_tx = server_tx.clone();
+            client_set.spawn(async move { client.listen(server_tx).await });
+
+            // Trace replay takes precedence over a client's own traffic
+            // section, which in turn takes precedence over the shared
+            // profile it refers to
+            let traffic = client_config.traffic.or_else(|| {
+                let name = client_config.traffic_profile.as_ref()?;
+                let profile = traffic_profiles.get(name).cloned();
+                if profile.is_none() {
+                    eprintln!(
+                        "No traffic profile named \"{name}\" for client \"{}\"",
+                        &client_config.id
+                    );
+                }
+                profile
+            });
+            let workload = match (trace_sends.remove(&client_config.id), traffic) {
+                (Some(sends), _) => Workload::Trace(sends),
+                (None, Some(traffic)) => Workload::Traffic(traffic),
+                (None, None) => Workload::Idle,
+            };
+            let mut user = User::new(
+                &client_config.id,
+                client_tx,
+                workload,
+                rngs.stream(RngStream::Traffic, &client_config.id),
+            );
+            if let Some(delivery_rx) = delivery_rx {
+                user = user.with_delivery_reports(delivery_rx);
+            }
+            if let Some((inbox_rx, auto_reply)) = inbox {
+                user = user.with_inbox(inbox_rx, auto_reply);
+            }
+            let all_ids = all_ids.clone();
+            let graph = graph.clone();
+            user_set.spawn(async move { user.run(&all_ids, graph.as_ref().as_ref()).await });
+        }
+
+        // Run until the configured duration elapses, the message limit
+        // is reached, or the user hits ctrl-c
+        tokio::select! {
+            _ = async {
+                match duration {
+                    Some(duration) => sleep(duration).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                println!("Reached run duration of {}ms", duration.unwrap_or_default().as_millis());
+            }
+            _ = stats.message_limit_reached() => {
+                println!("Reached limit of {} messages", max_messages.unwrap_or_default());
+            }
+            res = async {
+                match self.handle_ctrl_c {
+                    true => signal::ctrl_c().await,
+                    false => std::future::pending().await,
+                }
+            } => {
+                if let Err(e) = res {
+                    eprintln!("Failed listening for ctrl-c: {e}");
+                }
+                println!("Received ctrl-c");
+            }
+        }
+
+        // Stop users from sending any further messages
+        println!("Terminating tasks");
+        user_set.abort_all();
+        while let Some(res) = user_set.join_next().await {
+            match res {
+                Err(e) if !e.is_cancelled() => eprintln!("User exited: {e}"),
+                _ => {}
+            }
+        }
+
+        // Give in-flight packets a chance to reach their destination,
+        // anything still in flight afterwards is counted as lost
+        if timeout(drain, async {
+            while stats.in_flight() > 0 {
+                sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        })
+        .await
+        .is_err()
+        {
+            println!(
+                "{} messages still in flight after draining for {}ms",
+                stats.in_flight(),
+                drain.as_millis()
+            );
+        }
+
+        // Shut down clients and the nodes they route through, then the
+        // server and directory they all rely on
+        shut_down(
+            "client",
+            client_txs,
+            || ClientCommand::Shutdown,
+            &mut client_set,
+        )
+        .await;
+        shut_down(
+            "mix node",
+            mix_node_txs,
+            || MixNodeCommand::Shutdown,
+            &mut mix_node_set,
+        )
+        .await;
+        shut_down(
+            "provider",
+            provider_txs,
+            || ProviderCommand::Shutdown,
+            &mut provider_set,
+        )
+        .await;
+        server.abort();
+        directory.abort();
+        println!("Termination completed");
+
+        Ok(stats.summary())
+    }
+}
+
+// How a node's queue handles overflow, dropping the newest packet
+// unless configured otherwise; a queue that blocks senders until there
+// is room needs nothing set up
+fn overflow(
+    id: &str,
+    log_prefix: &str,
+    policy: Option<OverflowPolicy>,
+    rngs: &Rngs,
+    stats: &Stats,
+    mf: &Option<MetricFamilies>,
+) -> Option<Overflow> {
+    let policy = policy.unwrap_or_default();
+    if policy == OverflowPolicy::Block {
+        return None;
+    }
+    let overflow = Overflow::new(
+        id,
+        log_prefix,
+        policy,
+        rngs.stream(RngStream::Overflow, id),
+        stats.clone(),
+    );
+    Some(match mf {
+        Some(mf) => overflow.with_metrics(mf),
+        None => overflow,
+    })
+}
+
+// Sends every node of one kind the shutdown command, then waits for
+// them to exit, aborting any that take too long; sending counts
+// towards the timeout too, as a node stuck with a full queue never
+// makes room for the command
+async fn shut_down<C: Droppable>(
+    kind: &str,
+    txs: Vec<QueueSender<C>>,
+    shutdown: fn() -> C,
+    set: &mut JoinSet<()>,
+) {
+    if timeout(SHUTDOWN_TIMEOUT, async {
+        for tx in txs {
+            if let Err(e) = tx.send(shutdown()).await {
+                eprintln!("Failed sending shutdown to {kind}: {e}");
+            }
+        }
+        while let Some(res) = set.join_next().await {
+            if let Err(e) = res {
+                eprintln!("A {kind} exited: {e}");
+            }
+        }
+    })
+    .await
+    .is_err()
+    {
+        eprintln!("Every {kind} that did not shut down in time is being aborted");
+        set.abort_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with_traffic(traffic: &str) -> Result<Summary, SimulationError> {
+        let config = serde_yaml::from_str::<Config>(&format!(
+            "clients:\n- id: alice\n  traffic:\n    {traffic}\n- id: bob\n"
+        ))
+        .unwrap();
+        Simulation::builder()
+            .config(config)
+            .clock_mode(ClockMode::Simulated)
+            .build()
+            .run_blocking()
+    }
+
+    #[test]
+    fn rejects_traffic_without_positive_interval() {
+        for traffic in [
+            "interval_millis: 0",
+            "rate_per_second: 0.0",
+            "rate_per_second: -1.0",
+            "rate_per_second: .nan",
+            "rate_per_second: .inf",
+            "{ interval_millis: 0, schedule: exponential }",
+        ] {
+            assert!(
+                matches!(run_with_traffic(traffic), Err(SimulationError::Config(_))),
+                "{traffic}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_diurnal_schedules_that_never_send() {
+        for schedule in [
+            "{ period_millis: 0, amplitude: 0.5 }",
+            "{ amplitude: 1.0 }",
+            "{ amplitude: -0.5 }",
+        ] {
+            assert!(
+                matches!(
+                    run_with_traffic(&format!("schedule: !diurnal {schedule}")),
+                    Err(SimulationError::Config(_))
+                ),
+                "{schedule}"
+            );
+        }
+    }
+}
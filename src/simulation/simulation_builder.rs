@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use crate::{
+    config::{Client, Clock, ClockMode, Config, Metrics, Run},
+    simulation::Simulation,
+};
+
+// Assembles a simulation from a config, programmatic definitions, or
+// a mix of both; later calls override earlier ones
+#[derive(Default)]
+pub struct SimulationBuilder {
+    config: Config,
+    handle_ctrl_c: bool,
+}
+
+impl SimulationBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    pub fn clock_mode(mut self, mode: ClockMode) -> Self {
+        self.config.clock.get_or_insert(Clock { mode: None }).mode = Some(mode);
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.run_config().duration_millis = Some(duration.as_millis() as u64);
+        self
+    }
+
+    pub fn max_messages(mut self, max_messages: u64) -> Self {
+        self.run_config().max_messages = Some(max_messages);
+        self
+    }
+
+    pub fn drain(mut self, drain: Duration) -> Self {
+        self.run_config().drain_millis = Some(drain.as_millis() as u64);
+        self
+    }
+
+    pub fn metrics(mut self, enable: bool) -> Self {
+        self.metrics_config().enable = Some(enable);
+        self
+    }
+
+    // Address to serve metrics at, if they are enabled
+    pub fn metrics_address(mut self, address: &str) -> Self {
+        self.metrics_config().address = Some(address.to_owned());
+        self
+    }
+
+    // Adds a client, along with the user driving it
+    pub fn client(mut self, client: Client) -> Self {
+        self.config
+            .clients
+            .get_or_insert_with(Vec::new)
+            .push(client);
+        self
+    }
+
+    // Whether ctrl-c ends the run early; off by default since a
+    // library should not take over the process's signal handling
+    pub fn handle_ctrl_c(mut self, handle_ctrl_c: bool) -> Self {
+        self.handle_ctrl_c = handle_ctrl_c;
+        self
+    }
+
+    pub fn build(self) -> Simulation {
+        Simulation {
+            config: self.config,
+            handle_ctrl_c: self.handle_ctrl_c,
+        }
+    }
+
+    fn metrics_config(&mut self) -> &mut Metrics {
+        self.config.metrics.get_or_insert(Metrics {
+            enable: None,
+            address: None,
+        })
+    }
+
+    fn run_config(&mut self) -> &mut Run {
+        self.config.run.get_or_insert(Run {
+            duration_millis: None,
+            max_messages: None,
+            drain_millis: None,
+        })
+    }
+}
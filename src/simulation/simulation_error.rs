@@ -0,0 +1,38 @@
+use std::{error::Error, fmt::Display, io};
+
+use crate::traffic::TraceError;
+
+#[derive(Debug)]
+pub enum SimulationError {
+    Runtime(io::Error),
+    Config(String),
+    Trace(String, TraceError),
+    // Metrics could not be served at the given address
+    Metrics(String, Box<dyn Error + Send + Sync>),
+}
+
+impl Error for SimulationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SimulationError::Runtime(e) => Some(e),
+            SimulationError::Config(_) => None,
+            SimulationError::Trace(_, e) => Some(e),
+            SimulationError::Metrics(_, e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationError::Runtime(e) => write!(f, "failed building runtime: {e}"),
+            SimulationError::Config(reason) => write!(f, "invalid config: {reason}"),
+            SimulationError::Trace(path, e) => {
+                write!(f, "failed loading trace at \"{}\": {e}", &path)
+            }
+            SimulationError::Metrics(address, e) => {
+                write!(f, "failed serving metrics at \"{address}\": {e}")
+            }
+        }
+    }
+}
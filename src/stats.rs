@@ -0,0 +1,319 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::Notify, time::Instant};
+
+use crate::packet::PacketKind;
+
+// Running sums over delivered messages
+#[derive(Default)]
+struct Deliveries {
+    latency_millis: f64,
+    // Base-2 logarithm of the number of messages in flight when each
+    // message was delivered
+    anonymity_bits: f64,
+}
+
+// Ids of acknowledged messages by outcome; since a message may arrive
+// more than once, and its sender may give up on it even though it did
+// arrive, each one is counted as delivered or dropped only once
+#[derive(Default)]
+struct ReliableOutcomes {
+    delivered: HashSet<[u8; 16]>,
+    given_up: HashSet<[u8; 16]>,
+}
+
+// Fragments of a message split over several packets; the message is
+// lost once more of them are lost than it can do without
+struct FragmentedMessage {
+    spare: usize,
+    lost: usize,
+}
+
+// Run-wide message counters shared by every actor; unlike the
+// Prometheus metrics these are always collected, since they back the
+// end-of-run summary
+#[derive(Clone)]
+pub struct Stats {
+    started_at: Instant,
+    sent: Arc<AtomicU64>,
+    delivered: Arc<AtomicU64>,
+    dropped: Arc<AtomicU64>,
+    // Packets carrying messages or fragments of them, retransmissions
+    // included, against which the bandwidth spent per message is told
+    packets: Arc<AtomicU64>,
+    deliveries: Arc<Mutex<Deliveries>>,
+    reliable: Arc<Mutex<ReliableOutcomes>>,
+    fragmented: Arc<Mutex<HashMap<u64, FragmentedMessage>>>,
+    max_messages: Option<u64>,
+    limit_reached: Arc<Notify>,
+}
+
+impl Stats {
+    pub fn new(max_messages: Option<u64>) -> Self {
+        Self {
+            started_at: Instant::now(),
+            sent: Default::default(),
+            delivered: Default::default(),
+            dropped: Default::default(),
+            packets: Default::default(),
+            deliveries: Default::default(),
+            reliable: Default::default(),
+            fragmented: Default::default(),
+            max_messages,
+            limit_reached: Default::default(),
+        }
+    }
+
+    // Time since the start of the run
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    // Whether the configured message limit has been reached, after
+    // which no new messages should be sent
+    pub fn at_message_limit(&self) -> bool {
+        self.max_messages
+            .is_some_and(|max| self.sent.load(Ordering::SeqCst) >= max)
+    }
+
+    pub fn record_sent(&self) {
+        let sent = self.sent.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.max_messages == Some(sent) {
+            self.limit_reached.notify_one();
+        }
+    }
+
+    pub fn record_packets(&self, count: usize) {
+        self.packets.fetch_add(count as u64, Ordering::SeqCst);
+    }
+
+    // Records a delivery along with the time the message was sent at;
+    // every message in flight at delivery time, this one included, is
+    // counted towards its anonymity set, making that an upper bound
+    pub fn record_delivered(&self, sent_at: Duration) {
+        let anonymity_set = self.in_flight().max(1);
+        self.delivered.fetch_add(1, Ordering::SeqCst);
+        if let Ok(mut deliveries) = self.deliveries.lock() {
+            deliveries.latency_millis +=
+                self.elapsed().saturating_sub(sent_at).as_secs_f64() * 1000.0;
+            deliveries.anonymity_bits += (anonymity_set as f64).log2();
+        }
+    }
+
+    // Records the arrival of a message sent with acknowledgements on,
+    // returning whether it is the first copy to arrive; a message its
+    // sender had already given up on no longer counts as dropped
+    pub fn record_reliable_delivered(&self, id: [u8; 16], sent_at: Duration) -> bool {
+        let Ok(mut reliable) = self.reliable.lock() else {
+            return false;
+        };
+        if !reliable.delivered.insert(id) {
+            return false;
+        }
+        if reliable.given_up.remove(&id) {
+            self.dropped.fetch_sub(1, Ordering::SeqCst);
+        }
+        drop(reliable);
+        self.record_delivered(sent_at);
+        true
+    }
+
+    // Records that a sender gave up on a message sent with
+    // acknowledgements on, which counts as dropped unless it arrived
+    pub fn record_given_up(&self, id: [u8; 16]) {
+        let Ok(mut reliable) = self.reliable.lock() else {
+            return;
+        };
+        if !reliable.delivered.contains(&id) && reliable.given_up.insert(id) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Records that a message was split into `total` fragments, any
+    // `needed` of which are enough to put it back together
+    pub fn record_fragmented(&self, id: u64, needed: usize, total: usize) {
+        if let Ok(mut fragmented) = self.fragmented.lock() {
+            fragmented.insert(
+                id,
+                FragmentedMessage {
+                    spare: total.saturating_sub(needed),
+                    lost: 0,
+                },
+            );
+        }
+    }
+
+    // Records a lost packet; only packets carrying messages, or
+    // fragments of them, count, cover traffic is never part of the
+    // summary
+    pub fn record_dropped(&self, kind: PacketKind) {
+        match kind {
+            PacketKind::Message => {
+                self.dropped.fetch_add(1, Ordering::SeqCst);
+            }
+            PacketKind::Fragment(id) => self.record_fragment_dropped(id),
+            _ => {}
+        }
+    }
+
+    // Forgets a fragmented message once its recipient has put it back
+    // together, no longer waiting on the fate of its other fragments
+    pub fn record_reassembled(&self, id: u64) {
+        if let Ok(mut fragmented) = self.fragmented.lock() {
+            fragmented.remove(&id);
+        }
+    }
+
+    // Records that a recipient gave up on putting a fragmented message
+    // back together, which counts as dropped unless it already has
+    pub fn record_abandoned(&self, id: u64) {
+        let Ok(mut fragmented) = self.fragmented.lock() else {
+            return;
+        };
+        if fragmented.remove(&id).is_some() {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // A fragmented message counts as dropped, once, as soon as too few
+    // of its fragments are left to put it back together
+    fn record_fragment_dropped(&self, id: u64) {
+        let Ok(mut fragmented) = self.fragmented.lock() else {
+            return;
+        };
+        let Some(message) = fragmented.get_mut(&id) else {
+            return;
+        };
+        message.lost += 1;
+        if message.lost > message.spare {
+            fragmented.remove(&id);
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Number of sent messages that have neither been delivered nor
+    // dropped yet
+    pub fn in_flight(&self) -> u64 {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let done = self.delivered.load(Ordering::SeqCst) + self.dropped.load(Ordering::SeqCst);
+        sent.saturating_sub(done)
+    }
+
+    // Resolves once the configured message limit has been reached,
+    // never resolving if there is no limit
+    pub async fn message_limit_reached(&self) {
+        match self.max_messages {
+            Some(_) => self.limit_reached.notified().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    // Snapshots the counters, counting anything still in flight as lost
+    pub fn summary(&self) -> Summary {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let delivered = self.delivered.load(Ordering::SeqCst);
+        let dropped = self.dropped.load(Ordering::SeqCst);
+        let packets = self.packets.load(Ordering::SeqCst);
+        let (mean_latency_millis, mean_anonymity_bits) = match self.deliveries.lock() {
+            Ok(deliveries) if delivered > 0 => (
+                Some(deliveries.latency_millis / delivered as f64),
+                Some(deliveries.anonymity_bits / delivered as f64),
+            ),
+            _ => (None, None),
+        };
+        Summary {
+            sent,
+            delivered,
+            dropped,
+            lost: sent.saturating_sub(delivered + dropped),
+            packets,
+            mean_latency_millis,
+            mean_anonymity_bits,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub sent: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+    pub lost: u64,
+    pub packets: u64,
+    pub mean_latency_millis: Option<f64>,
+    // Mean upper bound on each delivered message's sender entropy
+    pub mean_anonymity_bits: Option<f64>,
+}
+
+impl Summary {
+    pub fn delivery_ratio(&self) -> f64 {
+        if self.sent == 0 {
+            0.0
+        } else {
+            self.delivered as f64 / self.sent as f64
+        }
+    }
+
+    // Bandwidth overhead of fragmentation, erasure coding and
+    // retransmission, one being a single packet per message
+    pub fn packets_per_message(&self) -> f64 {
+        if self.sent == 0 {
+            0.0
+        } else {
+            self.packets as f64 / self.sent as f64
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Messages sent:      {}", self.sent)?;
+        writeln!(f, "Messages delivered: {}", self.delivered)?;
+        writeln!(
+            f,
+            "Messages dropped:   {} ({} still in flight at shutdown)",
+            self.dropped + self.lost,
+            self.lost
+        )?;
+        writeln!(f, "Delivery ratio:     {:.4}", self.delivery_ratio())?;
+        writeln!(f, "Packets/message:    {:.2}", self.packets_per_message())?;
+        match self.mean_latency_millis {
+            Some(latency) => writeln!(f, "Mean latency:       {latency:.1}ms")?,
+            None => writeln!(f, "Mean latency:       n/a")?,
+        }
+        match self.mean_anonymity_bits {
+            Some(bits) => write!(f, "Mean anonymity:     {bits:.2} bits"),
+            None => write!(f, "Mean anonymity:     n/a"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_fragmented_messages_once_done() {
+        let stats = Stats::new(None);
+        stats.record_sent();
+        stats.record_sent();
+        stats.record_fragmented(1, 2, 3);
+        stats.record_fragmented(2, 2, 3);
+        stats.record_reassembled(1);
+        stats.record_abandoned(2);
+        assert!(stats.fragmented.lock().unwrap().is_empty());
+        assert_eq!(stats.dropped.load(Ordering::SeqCst), 1);
+        // Fragments of finished messages no longer count as dropped
+        stats.record_dropped(PacketKind::Fragment(2));
+        stats.record_abandoned(2);
+        assert_eq!(stats.dropped.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,40 @@
+use serde::Serialize;
+
+// Two-sided 95% critical values of Student's t distribution for 1 to
+// 30 degrees of freedom; beyond that the normal approximation is used
+const T_CRITICAL_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+const Z_CRITICAL_95: f64 = 1.960;
+
+// Sample mean of a metric over a grid point's runs, along with the
+// half-width of its 95% confidence interval
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct Estimate {
+    pub mean: Option<f64>,
+    pub ci95: Option<f64>,
+}
+
+impl Estimate {
+    pub fn from_samples(samples: &[f64]) -> Self {
+        let n = samples.len();
+        if n == 0 {
+            return Self::default();
+        }
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        if n < 2 {
+            return Self {
+                mean: Some(mean),
+                ci95: None,
+            };
+        }
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        let critical = T_CRITICAL_95.get(n - 2).copied().unwrap_or(Z_CRITICAL_95);
+        Self {
+            mean: Some(mean),
+            ci95: Some(critical * (variance / n as f64).sqrt()),
+        }
+    }
+}
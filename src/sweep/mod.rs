@@ -0,0 +1,9 @@
+mod estimate;
+mod sweep;
+mod sweep_error;
+mod sweep_spec;
+
+pub use estimate::Estimate;
+pub use sweep::{Sweep, SweepPoint};
+pub use sweep_error::SweepError;
+pub use sweep_spec::SweepSpec;
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    config::{self, load_config, Config, Metrics},
+    simulation::Simulation,
+    stats::Summary,
+    sweep::{Estimate, SweepError, SweepSpec},
+};
+
+const DEFAULT_CONFIG_PATH: &str = "./config";
+const DEFAULT_RUNS: u64 = 1;
+const CLIENT_COUNT_PARAMETER: &str = "client_count";
+
+// Aggregated results of every run at one combination of parameter
+// values
+#[derive(Serialize, Clone, Debug)]
+pub struct SweepPoint {
+    pub parameters: BTreeMap<String, Value>,
+    pub runs: usize,
+    pub delivery_ratio: Estimate,
+    pub packets_per_message: Estimate,
+    pub latency_millis: Estimate,
+    pub anonymity_bits: Estimate,
+}
+
+// Runs a simulation for every combination of swept parameter values
+// and seed, one after another
+pub struct Sweep {
+    spec: SweepSpec,
+    base: Config,
+}
+
+impl Sweep {
+    pub fn new(spec: SweepSpec, base: Config) -> Self {
+        Self { spec, base }
+    }
+
+    // Reads the spec at the given path along with the base config it
+    // refers to
+    pub fn from_file(path: &str, config_env_prefix: &str) -> Result<Self, SweepError> {
+        let contents = std::fs::read_to_string(path)?;
+        let spec: SweepSpec = serde_yaml::from_str(&contents).map_err(SweepError::Spec)?;
+        let config_path = spec.config_path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH);
+        let base = load_config(config_path, config_env_prefix).map_err(SweepError::Config)?;
+        Ok(Self::new(spec, base))
+    }
+
+    pub fn run(&self) -> Result<Vec<SweepPoint>, SweepError> {
+        let seeds = match &self.spec.seeds {
+            Some(seeds) => seeds.clone(),
+            None => (0..self.spec.runs.unwrap_or(DEFAULT_RUNS)).collect(),
+        };
+        let grid = self.grid();
+        let total = grid.len() * seeds.len();
+        let mut points = vec![];
+        for (i, parameters) in grid.into_iter().enumerate() {
+            let mut summaries = vec![];
+            for (j, seed) in seeds.iter().enumerate() {
+                println!(
+                    "[SWEEP] Run {}/{total}: {} with seed {seed}",
+                    i * seeds.len() + j + 1,
+                    describe(&parameters)
+                );
+                let mut config = self.configure(&parameters)?;
+                config.seed = Some(*seed);
+                // Runs follow each other too quickly to be scraped, and
+                // each would have to bind the metrics address again
+                config.metrics = Some(Metrics {
+                    enable: Some(false),
+                    address: None,
+                });
+                let summary = Simulation::builder()
+                    .config(config)
+                    .build()
+                    .run_blocking()
+                    .map_err(SweepError::Simulation)?;
+                summaries.push(summary);
+            }
+            points.push(aggregate(parameters, &summaries));
+        }
+        Ok(points)
+    }
+
+    // Writes the results to the spec's output path, returning that path
+    pub fn write(&self, points: &[SweepPoint]) -> Result<&str, SweepError> {
+        let output = &self.spec.output;
+        let contents = if output.ends_with(".json") {
+            serde_json::to_string_pretty(points).map_err(SweepError::Json)?
+        } else {
+            to_csv(self.spec.parameters.keys(), points)
+        };
+        std::fs::write(output, contents)?;
+        Ok(output)
+    }
+
+    // Cartesian product of every parameter's values
+    fn grid(&self) -> Vec<BTreeMap<String, Value>> {
+        let mut grid = vec![BTreeMap::new()];
+        for (key, values) in &self.spec.parameters {
+            grid = grid
+                .into_iter()
+                .flat_map(|point| {
+                    values.iter().map(move |value| {
+                        let mut point = point.clone();
+                        point.insert(key.clone(), value.clone());
+                        point
+                    })
+                })
+                .collect();
+        }
+        grid
+    }
+
+    // Applies a grid point's values to a copy of the base config; the
+    // client count goes first, so that other parameters can refer to
+    // the clients it creates
+    fn configure(&self, parameters: &BTreeMap<String, Value>) -> Result<Config, SweepError> {
+        let mut config = self.base.clone();
+        if let Some(value) = parameters.get(CLIENT_COUNT_PARAMETER) {
+            let count = value.as_u64().ok_or_else(|| {
+                SweepError::Parameter(
+                    CLIENT_COUNT_PARAMETER.to_owned(),
+                    "expected a non-negative integer".to_owned(),
+                )
+            })?;
+            self.resize_clients(&mut config, count as usize);
+        }
+        let mut tree = serde_json::to_value(&config).map_err(SweepError::Json)?;
+        for (key, value) in parameters {
+            if key != CLIENT_COUNT_PARAMETER {
+                set_path(&mut tree, key, value.clone())?;
+            }
+        }
+        serde_json::from_value(tree).map_err(|e| {
+            SweepError::Parameter(describe(parameters), format!("invalid config: {e}"))
+        })
+    }
+
+    // Keeps the first `count` configured clients, creating further ones
+    // named "client-<n>" if there are not enough
+    fn resize_clients(&self, config: &mut Config, count: usize) {
+        let clients = config.clients.get_or_insert_with(Vec::new);
+        clients.truncate(count);
+        let mut n = 0;
+        while clients.len() < count {
+            let id = format!("client-{n}");
+            n += 1;
+            if clients.iter().any(|client| client.id == id) {
+                continue;
+            }
+            clients.push(config::Client {
+                id,
+                traffic_profile: self.spec.client_traffic_profile.clone(),
+                ..Default::default()
+            });
+        }
+    }
+}
+
+// Sets the value at a dotted path such as "network.path_length",
+// creating any missing sections on the way; numeric segments index
+// into lists, e.g. "clients.0.traffic.interval_millis"
+fn set_path(tree: &mut Value, key: &str, value: Value) -> Result<(), SweepError> {
+    let mut node = tree;
+    for segment in key.split('.') {
+        if node.is_null() {
+            *node = Value::Object(Default::default());
+        }
+        node = match node {
+            Value::Object(map) => map.entry(segment).or_insert(Value::Null),
+            Value::Array(list) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| list.get_mut(index))
+                .ok_or_else(|| {
+                    SweepError::Parameter(key.to_owned(), format!("no list entry \"{segment}\""))
+                })?,
+            _ => {
+                return Err(SweepError::Parameter(
+                    key.to_owned(),
+                    format!("\"{segment}\" is not a config section"),
+                ))
+            }
+        };
+    }
+    *node = value;
+    Ok(())
+}
+
+fn aggregate(parameters: BTreeMap<String, Value>, summaries: &[Summary]) -> SweepPoint {
+    let delivery_ratios = summaries
+        .iter()
+        .map(Summary::delivery_ratio)
+        .collect::<Vec<f64>>();
+    let packets_per_message = summaries
+        .iter()
+        .map(Summary::packets_per_message)
+        .collect::<Vec<f64>>();
+    let latencies = summaries
+        .iter()
+        .filter_map(|summary| summary.mean_latency_millis)
+        .collect::<Vec<f64>>();
+    let anonymity_bits = summaries
+        .iter()
+        .filter_map(|summary| summary.mean_anonymity_bits)
+        .collect::<Vec<f64>>();
+    SweepPoint {
+        parameters,
+        runs: summaries.len(),
+        delivery_ratio: Estimate::from_samples(&delivery_ratios),
+        packets_per_message: Estimate::from_samples(&packets_per_message),
+        latency_millis: Estimate::from_samples(&latencies),
+        anonymity_bits: Estimate::from_samples(&anonymity_bits),
+    }
+}
+
+fn describe(parameters: &BTreeMap<String, Value>) -> String {
+    if parameters.is_empty() {
+        return "base config".to_owned();
+    }
+    parameters
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn to_csv<'a>(keys: impl Iterator<Item = &'a String>, points: &[SweepPoint]) -> String {
+    let keys = keys.collect::<Vec<&String>>();
+    let mut header = keys
+        .iter()
+        .map(|key| csv_field(key))
+        .collect::<Vec<String>>();
+    header.push("runs".to_owned());
+    for metric in [
+        "delivery_ratio",
+        "packets_per_message",
+        "latency_millis",
+        "anonymity_bits",
+    ] {
+        header.push(format!("{metric}_mean"));
+        header.push(format!("{metric}_ci95"));
+    }
+    let mut lines = vec![header.join(",")];
+    for point in points {
+        let mut fields = keys
+            .iter()
+            .map(|key| match point.parameters.get(*key) {
+                Some(Value::String(s)) => csv_field(s),
+                Some(value) => csv_field(&value.to_string()),
+                None => String::new(),
+            })
+            .collect::<Vec<String>>();
+        fields.push(point.runs.to_string());
+        for estimate in [
+            point.delivery_ratio,
+            point.packets_per_message,
+            point.latency_millis,
+            point.anonymity_bits,
+        ] {
+            for value in [estimate.mean, estimate.ci95] {
+                fields.push(value.map(|value| value.to_string()).unwrap_or_default());
+            }
+        }
+        lines.push(fields.join(","));
+    }
+    lines.join("\n") + "\n"
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
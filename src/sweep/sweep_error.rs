@@ -0,0 +1,47 @@
+use std::{error::Error, fmt::Display, io};
+
+use crate::{config::ConfigError, simulation::SimulationError};
+
+#[derive(Debug)]
+pub enum SweepError {
+    Io(io::Error),
+    Spec(serde_yaml::Error),
+    Config(ConfigError),
+    Parameter(String, String),
+    Simulation(SimulationError),
+    Json(serde_json::Error),
+}
+
+impl Error for SweepError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SweepError::Io(e) => Some(e),
+            SweepError::Spec(e) => Some(e),
+            SweepError::Config(e) => Some(e),
+            SweepError::Parameter(_, _) => None,
+            SweepError::Simulation(e) => Some(e),
+            SweepError::Json(e) => Some(e),
+        }
+    }
+}
+
+impl Display for SweepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SweepError::Io(e) => write!(f, "failed reading or writing sweep file: {e}"),
+            SweepError::Spec(e) => write!(f, "invalid sweep spec: {e}"),
+            SweepError::Config(e) => write!(f, "failed loading base config: {e}"),
+            SweepError::Parameter(key, reason) => {
+                write!(f, "invalid value for parameter \"{key}\": {reason}")
+            }
+            SweepError::Simulation(e) => write!(f, "run failed: {e}"),
+            SweepError::Json(e) => write!(f, "failed writing results: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for SweepError {
+    fn from(e: io::Error) -> Self {
+        SweepError::Io(e)
+    }
+}
@@ -0,0 +1,25 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Describes a batch of runs: every combination of parameter values is
+// run once per seed, on top of a shared base config
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SweepSpec {
+    // Directory holding the base config, "./config" by default
+    pub config_path: Option<String>,
+    // Seeds to run every grid point with; if absent, seeds 0 up to
+    // `runs` are used
+    pub seeds: Option<Vec<u64>>,
+    pub runs: Option<u64>,
+    // Values to sweep over, keyed by dotted config path such as
+    // "network.path_length", or "client_count" for the number of
+    // clients
+    pub parameters: BTreeMap<String, Vec<Value>>,
+    // Traffic profile given to clients created to reach `client_count`
+    pub client_traffic_profile: Option<String>,
+    // Where to write the results; written as JSON if the path ends in
+    // ".json", otherwise as CSV
+    pub output: String,
+}
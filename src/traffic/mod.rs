@@ -0,0 +1,13 @@
+mod recipient_selector;
+mod send_schedule;
+mod send_schedule_error;
+mod social_graph;
+mod trace;
+mod trace_error;
+
+pub use recipient_selector::RecipientSelector;
+pub use send_schedule::SendSchedule;
+pub use send_schedule_error::SendScheduleError;
+pub use social_graph::{zipf_weights, SocialGraph};
+pub use trace::{load_trace, schedule_trace, TraceSend};
+pub use trace_error::TraceError;
@@ -0,0 +1,74 @@
+use rand::{
+    distr::{weighted::WeightedIndex, Distribution},
+    rngs::StdRng,
+    seq::IndexedRandom,
+    seq::IteratorRandom,
+};
+
+use crate::{
+    config::Recipients,
+    traffic::{zipf_weights, SocialGraph},
+};
+
+// Resolves a user's configured recipients into the set of ids it
+// picks from whenever it sends a message
+pub struct RecipientSelector {
+    candidates: Vec<String>,
+    // Skewed pick among the candidates, uniform if unset
+    weights: Option<WeightedIndex<f64>>,
+}
+
+impl RecipientSelector {
+    pub fn new(
+        recipients: &Recipients,
+        own_id: &str,
+        all_ids: &[String],
+        graph: Option<&SocialGraph>,
+        rng: &mut StdRng,
+    ) -> Self {
+        let others = all_ids.iter().filter(|&id| id != own_id);
+        let mut weights = None;
+        let mut candidates = match recipients {
+            Recipients::Fixed(ids) => ids.clone(),
+            Recipients::Random(count) => others.cloned().choose_multiple(rng, *count),
+            Recipients::All => others.cloned().collect(),
+            Recipients::Contacts => match graph {
+                Some(graph) => {
+                    let contacts = graph.contacts_by_popularity(own_id);
+                    weights =
+                        WeightedIndex::new(zipf_weights(contacts.len(), graph.popularity_skew()))
+                            .ok();
+                    contacts
+                }
+                None => {
+                    eprintln!(
+                        "[USER][{own_id}] Recipients are contacts but no social graph is configured"
+                    );
+                    vec![]
+                }
+            },
+        };
+
+        // A user without anyone else to talk to sends to itself
+        if candidates.is_empty() {
+            candidates.push(own_id.to_owned());
+            weights = None;
+        }
+
+        Self {
+            candidates,
+            weights,
+        }
+    }
+
+    pub fn choose(&self, rng: &mut StdRng) -> &str {
+        match &self.weights {
+            Some(weights) => &self.candidates[weights.sample(rng)],
+            None => self
+                .candidates
+                .choose(rng)
+                .map(String::as_str)
+                .unwrap_or_default(),
+        }
+    }
+}
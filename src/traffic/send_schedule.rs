@@ -0,0 +1,158 @@
+use std::{f64::consts::PI, time::Duration};
+
+use rand::{rngs::StdRng, Rng};
+use rand_distr::{Distribution, Exp, Pareto};
+
+use crate::{
+    config::{Schedule, Traffic},
+    traffic::SendScheduleError,
+};
+
+const DEFAULT_SEND_INTERVAL_MILLIS: u64 = 5000;
+const DEFAULT_DIURNAL_PERIOD_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+enum Kind {
+    Fixed,
+    Exponential,
+    ParetoOnOff {
+        on: Pareto<f64>,
+        off: Pareto<f64>,
+        on_until: Option<f64>,
+    },
+    Diurnal {
+        period: f64,
+        peak: f64,
+        amplitude: f64,
+    },
+}
+
+// Decides how long a user waits between consecutive sends; all times
+// are handled in milliseconds since the user started sending
+pub struct SendSchedule {
+    kind: Kind,
+    mean_interval: f64,
+}
+
+impl SendSchedule {
+    // The schedule a traffic config asks for, sending every
+    // `interval_millis`, or `rate_per_second` times a second, on average
+    pub fn for_traffic(traffic: &Traffic) -> Result<Self, SendScheduleError> {
+        let mean_interval = match (traffic.interval_millis, traffic.rate_per_second) {
+            (Some(interval_millis), _) => Duration::from_millis(interval_millis),
+            (None, Some(rate)) if rate > 0.0 => Duration::from_secs_f64(1.0 / rate),
+            _ => Duration::from_millis(DEFAULT_SEND_INTERVAL_MILLIS),
+        };
+        Self::new(
+            traffic.schedule.as_ref().unwrap_or(&Schedule::Fixed),
+            mean_interval,
+        )
+    }
+
+    // Fails for schedules that would never yield a delay above zero,
+    // which would have the user send forever without time passing
+    pub fn new(schedule: &Schedule, mean_interval: Duration) -> Result<Self, SendScheduleError> {
+        if mean_interval.is_zero() {
+            return Err(SendScheduleError::MeanInterval);
+        }
+        let kind = match schedule {
+            Schedule::Fixed => Kind::Fixed,
+            Schedule::Exponential => Kind::Exponential,
+            Schedule::ParetoOnOff {
+                shape,
+                on_scale_millis,
+                off_scale_millis,
+            } => {
+                match (
+                    Pareto::new(*on_scale_millis as f64, *shape),
+                    Pareto::new(*off_scale_millis as f64, *shape),
+                ) {
+                    (Ok(on), Ok(off)) => Kind::ParetoOnOff {
+                        on,
+                        off,
+                        on_until: None,
+                    },
+                    _ => {
+                        eprintln!("[TRAFFIC] Invalid Pareto on/off parameters, sending exponentially instead");
+                        Kind::Exponential
+                    }
+                }
+            }
+            // Thinning rejects ever more candidates as the rate's
+            // trough approaches zero
+            Schedule::Diurnal {
+                period_millis,
+                peak_millis,
+                amplitude,
+            } => {
+                if *period_millis == Some(0) {
+                    return Err(SendScheduleError::DiurnalPeriod);
+                }
+                if !(0.0..1.0).contains(amplitude) {
+                    return Err(SendScheduleError::DiurnalAmplitude(*amplitude));
+                }
+                Kind::Diurnal {
+                    period: period_millis.unwrap_or(DEFAULT_DIURNAL_PERIOD_MILLIS) as f64,
+                    peak: peak_millis.unwrap_or_default() as f64,
+                    amplitude: *amplitude,
+                }
+            }
+        };
+        Ok(Self {
+            kind,
+            mean_interval: mean_interval.as_secs_f64() * 1000.0,
+        })
+    }
+
+    // Returns how long to wait until the next send, given how long the
+    // user has been sending for
+    pub fn next_delay(&mut self, elapsed: Duration, rng: &mut StdRng) -> Duration {
+        let now = elapsed.as_secs_f64() * 1000.0;
+        let next = match &mut self.kind {
+            Kind::Fixed => now + self.mean_interval,
+            Kind::Exponential => now + sample_exp(self.mean_interval, rng),
+            Kind::ParetoOnOff {
+                on,
+                off,
+                on_until: state,
+            } => {
+                let mut on_until = *state.get_or_insert_with(|| now + on.sample(rng));
+                let mut next = now + sample_exp(self.mean_interval, rng);
+                // Sends that would fall into an off period are pushed
+                // into the following on period instead
+                while next >= on_until {
+                    let on_start = on_until + off.sample(rng);
+                    on_until = on_start + on.sample(rng);
+                    next = on_start + sample_exp(self.mean_interval, rng);
+                }
+                *state = Some(on_until);
+                next
+            }
+            // Thinning: draw candidates at the peak rate and accept each
+            // in proportion to the rate at the candidate's time
+            Kind::Diurnal {
+                period,
+                peak,
+                amplitude,
+            } => {
+                let max_mean_interval = self.mean_interval / (1.0 + *amplitude);
+                let mut next = now;
+                loop {
+                    next += sample_exp(max_mean_interval, rng);
+                    let phase = 2.0 * PI * (next - *peak) / *period;
+                    let relative_rate = (1.0 + *amplitude * phase.cos()) / (1.0 + *amplitude);
+                    if rng.random::<f64>() < relative_rate {
+                        break next;
+                    }
+                }
+            }
+        };
+        Duration::from_secs_f64((next - now).max(0.0) / 1000.0)
+    }
+}
+
+fn sample_exp(mean: f64, rng: &mut StdRng) -> f64 {
+    match Exp::new(1.0 / mean) {
+        Ok(exp) if mean > 0.0 => exp.sample(rng),
+        _ => 0.0,
+    }
+}
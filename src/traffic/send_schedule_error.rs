@@ -0,0 +1,27 @@
+use std::{error::Error, fmt::Display};
+
+#[derive(Debug)]
+pub enum SendScheduleError {
+    MeanInterval,
+    DiurnalPeriod,
+    DiurnalAmplitude(f64),
+}
+
+impl Error for SendScheduleError {}
+
+impl Display for SendScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendScheduleError::MeanInterval => {
+                write!(f, "sends need a mean interval above zero")
+            }
+            SendScheduleError::DiurnalPeriod => {
+                write!(f, "diurnal schedules need a period above zero")
+            }
+            SendScheduleError::DiurnalAmplitude(amplitude) => write!(
+                f,
+                "diurnal schedules need an amplitude of at least 0 and below 1, got {amplitude}"
+            ),
+        }
+    }
+}
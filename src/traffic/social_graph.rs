@@ -0,0 +1,180 @@
+use std::collections::{BTreeSet, HashMap};
+
+use rand::{
+    distr::{weighted::WeightedIndex, Distribution},
+    rngs::StdRng,
+    seq::SliceRandom,
+    Rng,
+};
+
+use crate::config::{GraphModel, SocialGraph as SocialGraphConfig};
+
+// Undirected contact graph between users, which users with contact
+// based recipients pick from
+pub struct SocialGraph {
+    contacts: HashMap<String, BTreeSet<String>>,
+    popularity_skew: f64,
+}
+
+impl SocialGraph {
+    pub fn new(config: &SocialGraphConfig, ids: &[String], rng: &mut StdRng) -> Self {
+        let mut graph = Self {
+            contacts: ids.iter().map(|id| (id.clone(), BTreeSet::new())).collect(),
+            popularity_skew: config.popularity_skew.unwrap_or_default(),
+        };
+        match &config.model {
+            GraphModel::ErdosRenyi { p } => graph.generate_erdos_renyi(ids, *p, rng),
+            GraphModel::BarabasiAlbert { m } => graph.generate_barabasi_albert(ids, *m, rng),
+            GraphModel::Zipf { contacts, exponent } => {
+                graph.generate_zipf(ids, *contacts, *exponent, rng)
+            }
+            GraphModel::EdgeList { path } => graph.load_edge_list(path),
+        }
+        graph
+    }
+
+    // Returns the user's contacts, most popular (best connected) first
+    pub fn contacts_by_popularity(&self, id: &str) -> Vec<String> {
+        let mut contacts = self
+            .contacts
+            .get(id)
+            .map(|contacts| contacts.iter().cloned().collect::<Vec<String>>())
+            .unwrap_or_default();
+        contacts.sort_by_key(|contact| std::cmp::Reverse(self.degree(contact)));
+        contacts
+    }
+
+    pub fn popularity_skew(&self) -> f64 {
+        self.popularity_skew
+    }
+
+    fn degree(&self, id: &str) -> usize {
+        self.contacts.get(id).map(BTreeSet::len).unwrap_or_default()
+    }
+
+    fn connect(&mut self, a: &str, b: &str) {
+        if a == b {
+            return;
+        }
+        if let Some(unknown) = [a, b]
+            .into_iter()
+            .find(|id| !self.contacts.contains_key(*id))
+        {
+            eprintln!(
+                "[GRAPH] Ignoring edge between \"{a}\" and \"{b}\": \"{unknown}\" is not a known client"
+            );
+            return;
+        }
+        for (from, to) in [(a, b), (b, a)] {
+            if let Some(contacts) = self.contacts.get_mut(from) {
+                contacts.insert(to.to_owned());
+            }
+        }
+    }
+
+    fn generate_erdos_renyi(&mut self, ids: &[String], p: f64, rng: &mut StdRng) {
+        for (i, a) in ids.iter().enumerate() {
+            for b in &ids[i + 1..] {
+                if rng.random_bool(p.clamp(0.0, 1.0)) {
+                    self.connect(a, b);
+                }
+            }
+        }
+    }
+
+    // Starts from a fully connected core of `m + 1` clients, then adds
+    // every further client with `m` edges to existing clients picked
+    // proportionally to their degree
+    fn generate_barabasi_albert(&mut self, ids: &[String], m: usize, rng: &mut StdRng) {
+        let core = (m + 1).min(ids.len());
+        // Every client appears here once per edge end it has, so that a
+        // uniform pick from it is a degree-proportional pick
+        let mut edge_ends = vec![];
+        for (i, a) in ids[..core].iter().enumerate() {
+            for b in &ids[i + 1..core] {
+                self.connect(a, b);
+                edge_ends.push(a.clone());
+                edge_ends.push(b.clone());
+            }
+        }
+        for id in &ids[core..] {
+            let mut targets = BTreeSet::new();
+            while targets.len() < m.min(edge_ends.len()) {
+                targets.insert(edge_ends[rng.random_range(0..edge_ends.len())].clone());
+            }
+            for target in targets {
+                self.connect(id, &target);
+                edge_ends.push(id.clone());
+                edge_ends.push(target);
+            }
+        }
+    }
+
+    // Ranks clients by a random global popularity order, then has every
+    // client draw distinct contacts with probability proportional to
+    // `1 / rank^exponent`; clients whose weight underflows to zero are
+    // never drawn, so a client may end up with fewer contacts
+    fn generate_zipf(&mut self, ids: &[String], contacts: usize, exponent: f64, rng: &mut StdRng) {
+        let mut ranked = ids.to_vec();
+        ranked.shuffle(rng);
+        let weights = zipf_weights(ranked.len(), exponent);
+        for id in ids {
+            // Sample without replacement by zeroing the weight of every
+            // client already drawn, starting with the client itself
+            let mut weights = weights.clone();
+            if let Some(rank) = ranked.iter().position(|other| other == id) {
+                weights[rank] = 0.0;
+            }
+            let candidates = weights.iter().filter(|&&weight| weight > 0.0).count();
+            let wanted = contacts.min(candidates);
+            if wanted == 0 {
+                continue;
+            }
+            let Ok(mut popularity) = WeightedIndex::new(&weights) else {
+                continue;
+            };
+            let mut picked = BTreeSet::new();
+            loop {
+                let rank = popularity.sample(rng);
+                picked.insert(ranked[rank].clone());
+                if picked.len() == wanted || popularity.update_weights(&[(rank, &0.0)]).is_err() {
+                    break;
+                }
+            }
+            for contact in picked {
+                self.connect(id, &contact);
+            }
+        }
+    }
+
+    fn load_edge_list(&mut self, path: &str) {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("[GRAPH] Failed reading edge list at \"{path}\": {e}");
+                return;
+            }
+        };
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let ids = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|id| !id.is_empty())
+                .collect::<Vec<&str>>();
+            match ids[..] {
+                [a, b] => self.connect(a, b),
+                _ => eprintln!("[GRAPH] Ignoring malformed edge \"{line}\" in \"{path}\""),
+            }
+        }
+    }
+}
+
+// Weights `1 / rank^exponent` for ranks 1 through `count`
+pub fn zipf_weights(count: usize, exponent: f64) -> Vec<f64> {
+    (1..=count)
+        .map(|rank| 1.0 / (rank as f64).powf(exponent))
+        .collect()
+}
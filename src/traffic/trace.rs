@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{
+    config::{Trace as TraceConfig, TraceFormat},
+    traffic::TraceError,
+};
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TraceEvent {
+    // Seconds, relative to any fixed origin
+    pub timestamp: f64,
+    pub sender: String,
+    pub recipient: String,
+    // Message body size in bytes
+    pub size: usize,
+    // Overrides the network's path length for this message
+    pub path_length: Option<usize>,
+}
+
+// A message taken from a trace, scheduled relative to the start of
+// the simulation
+#[derive(Clone, Debug)]
+pub struct TraceSend {
+    pub at: Duration,
+    pub recipient: String,
+    pub size: usize,
+    pub path_length: Option<usize>,
+}
+
+// Reads every event in the configured trace, sorted by timestamp
+pub fn load_trace(config: &TraceConfig) -> Result<Vec<TraceEvent>, TraceError> {
+    let contents = std::fs::read_to_string(&config.path)?;
+    let format = config.format.unwrap_or_else(|| {
+        if config.path.ends_with(".csv") {
+            TraceFormat::Csv
+        } else {
+            TraceFormat::Jsonl
+        }
+    });
+    let mut events = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let event = match format {
+            TraceFormat::Jsonl => {
+                serde_json::from_str(line).map_err(|e| TraceError::Json(i + 1, e))?
+            }
+            TraceFormat::Csv => match parse_csv_line(i + 1, line)? {
+                Some(event) => event,
+                None => continue,
+            },
+        };
+        events.push(event);
+    }
+    events.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+    Ok(events)
+}
+
+// Maps the events' timestamps onto simulation time, with the first
+// event at the start of the simulation, and groups them per sender;
+// `speed` must be finite and positive
+pub fn schedule_trace(events: &[TraceEvent], speed: f64) -> Vec<(String, TraceSend)> {
+    let origin = events
+        .first()
+        .map(|event| event.timestamp)
+        .unwrap_or_default();
+    events
+        .iter()
+        .map(|event| {
+            (
+                event.sender.clone(),
+                TraceSend {
+                    at: Duration::from_secs_f64(((event.timestamp - origin) / speed).max(0.0)),
+                    recipient: event.recipient.clone(),
+                    size: event.size,
+                    path_length: event.path_length,
+                },
+            )
+        })
+        .collect()
+}
+
+// Parses a `timestamp,sender,recipient,size[,path_length]` line,
+// skipping a header
+fn parse_csv_line(line_number: usize, line: &str) -> Result<Option<TraceEvent>, TraceError> {
+    let fields = line.split(',').map(str::trim).collect::<Vec<&str>>();
+    let (timestamp, sender, recipient, size, path_length) = match fields[..] {
+        [timestamp, sender, recipient, size] => (timestamp, sender, recipient, size, ""),
+        [timestamp, sender, recipient, size, path_length] => {
+            (timestamp, sender, recipient, size, path_length)
+        }
+        _ => {
+            return Err(TraceError::Csv(
+                line_number,
+                format!("expected 4 or 5 fields, found {}", fields.len()),
+            ))
+        }
+    };
+    let Some(timestamp) = timestamp
+        .parse::<f64>()
+        .ok()
+        .filter(|timestamp| timestamp.is_finite())
+    else {
+        if line_number == 1 {
+            return Ok(None);
+        }
+        return Err(TraceError::Csv(
+            line_number,
+            format!("invalid timestamp \"{timestamp}\""),
+        ));
+    };
+    let size = size
+        .parse::<usize>()
+        .map_err(|_| TraceError::Csv(line_number, format!("invalid size \"{size}\"")))?;
+    let path_length = match path_length {
+        "" => None,
+        path_length => Some(path_length.parse::<usize>().map_err(|_| {
+            TraceError::Csv(
+                line_number,
+                format!("invalid path length \"{path_length}\""),
+            )
+        })?),
+    };
+    Ok(Some(TraceEvent {
+        timestamp,
+        sender: sender.to_owned(),
+        recipient: recipient.to_owned(),
+        size,
+        path_length,
+    }))
+}
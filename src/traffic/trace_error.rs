@@ -0,0 +1,34 @@
+use std::{error::Error, fmt::Display, io};
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    Json(usize, serde_json::Error),
+    Csv(usize, String),
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::Io(e) => Some(e),
+            TraceError::Json(_, e) => Some(e),
+            TraceError::Csv(_, _) => None,
+        }
+    }
+}
+
+impl Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "failed reading trace: {e}"),
+            TraceError::Json(line, e) => write!(f, "invalid JSON on line {line}: {e}"),
+            TraceError::Csv(line, reason) => write!(f, "invalid CSV on line {line}: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        TraceError::Io(e)
+    }
+}
@@ -1,25 +1,77 @@
-use std::time::Duration;
+use std::{collections::VecDeque, time::Duration};
 
-use crate::client::{ClientCommand, ClientSendError};
+use crate::{
+    client::{ClientCommand, ClientSendError, DeliveryReport, DeliveryStatus, ReceivedMessage},
+    config::{MessageSize, Recipients, Traffic},
+    queue::QueueSender,
+    traffic::{RecipientSelector, SendSchedule, SocialGraph, TraceSend},
+};
+use rand::{rngs::StdRng, Rng};
 use tokio::{
-    sync::mpsc::{self, Sender as MpscSender},
-    time::sleep,
+    sync::mpsc::{self, Receiver as MpscReceiver},
+    time::{sleep_until, Instant},
 };
 
+// What drives a user's sends
+pub enum Workload {
+    Idle,
+    Traffic(Traffic),
+    // Sends taken from a message trace, ordered by time
+    Trace(Vec<TraceSend>),
+}
+
 pub struct User {
     id: String,
-    client_tx: MpscSender<ClientCommand>,
+    client_tx: QueueSender<ClientCommand>,
+    workload: Workload,
+    rng: StdRng,
+    delivery_rx: Option<MpscReceiver<DeliveryReport>>,
+    inbox_rx: Option<MpscReceiver<ReceivedMessage>>,
+    // Messages that arrived while the user was waiting on its client,
+    // handled the next time it waits
+    backlog: VecDeque<ReceivedMessage>,
+    auto_reply: bool,
 }
 
 impl User {
-    pub fn new(id: &str, client_tx: MpscSender<ClientCommand>) -> Self {
+    pub fn new(
+        id: &str,
+        client_tx: QueueSender<ClientCommand>,
+        workload: Workload,
+        rng: StdRng,
+    ) -> Self {
         Self {
             id: id.to_owned(),
             client_tx,
+            workload,
+            rng,
+            delivery_rx: None,
+            inbox_rx: None,
+            backlog: VecDeque::new(),
+            auto_reply: false,
         }
     }
 
-    pub async fn send_loop(&mut self, to: &str, body: &str, interval_millis: u64) {
+    // Has the user hear back from its client whether its messages were
+    // delivered
+    pub fn with_delivery_reports(mut self, delivery_rx: MpscReceiver<DeliveryReport>) -> Self {
+        self.delivery_rx = Some(delivery_rx);
+        self
+    }
+
+    // Has the user hear from its client about the messages it receives,
+    // answering every one that comes with a SURB if `auto_reply` is set
+    pub fn with_inbox(mut self, inbox_rx: MpscReceiver<ReceivedMessage>, auto_reply: bool) -> Self {
+        self.inbox_rx = Some(inbox_rx);
+        self.auto_reply = auto_reply;
+        self
+    }
+
+    // Registers the user's client in the directory and then sends
+    // messages according to the user's workload; `all_ids` lists every
+    // user that could be sent to
+    pub async fn run(&mut self, all_ids: &[String], graph: Option<&SocialGraph>) {
+        let started_at = Instant::now();
         let cmd = ClientCommand::Register;
         if let Err(e) = self.client_tx.send(cmd).await {
             eprintln!(
@@ -29,40 +81,251 @@ impl User {
             return;
         }
 
+        let reports = log_delivery_reports(self.id.clone(), self.delivery_rx.take());
+        let workload = async {
+            match std::mem::replace(&mut self.workload, Workload::Idle) {
+                Workload::Idle => {}
+                Workload::Traffic(traffic) => {
+                    self.send_traffic(traffic, started_at, all_ids, graph).await
+                }
+                Workload::Trace(sends) => self.replay_trace(sends, started_at).await,
+            }
+            // Keep answering messages once the workload is done
+            self.wait(None).await;
+        };
+        tokio::join!(workload, reports);
+    }
+
+    async fn send_traffic(
+        &mut self,
+        traffic: Traffic,
+        started_at: Instant,
+        all_ids: &[String],
+        graph: Option<&SocialGraph>,
+    ) {
+        let recipients = RecipientSelector::new(
+            traffic.recipients.as_ref().unwrap_or(&Recipients::All),
+            &self.id,
+            all_ids,
+            graph,
+            &mut self.rng,
+        );
+        let mut schedule = match SendSchedule::for_traffic(&traffic) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                eprintln!("[USER][{}] Not sending any traffic: {e}", &self.id);
+                return;
+            }
+        };
+        let stop_at = traffic
+            .stop_millis
+            .map(|stop_millis| started_at + Duration::from_millis(stop_millis));
+
+        let start = Duration::from_millis(traffic.start_millis.unwrap_or_default());
+        self.wait(Some(Instant::now() + start)).await;
+        while stop_at.is_none_or(|stop_at| Instant::now() < stop_at) {
+            let to = recipients.choose(&mut self.rng).to_owned();
+            let body = generate_body(&to, traffic.message_size.as_ref(), &mut self.rng);
+            self.send(&to, &body, traffic.path_length).await;
+            let elapsed = Instant::now()
+                .duration_since(started_at)
+                .saturating_sub(start);
+            let delay = schedule.next_delay(elapsed, &mut self.rng);
+            self.wait(Some(Instant::now() + delay)).await;
+        }
+    }
+
+    async fn replay_trace(&mut self, sends: Vec<TraceSend>, started_at: Instant) {
+        for trace_send in sends {
+            self.wait(Some(started_at + trace_send.at)).await;
+            let size = MessageSize {
+                min_bytes: trace_send.size,
+                max_bytes: trace_send.size,
+            };
+            let body = generate_body(&trace_send.recipient, Some(&size), &mut self.rng);
+            self.send(&trace_send.recipient, &body, trace_send.path_length)
+                .await;
+        }
+    }
+
+    // Waits until the given time, or until the client goes away if no
+    // time is given, handling the messages received meanwhile
+    async fn wait(&mut self, until: Option<Instant>) {
+        while let Some(message) = self.backlog.pop_front() {
+            self.receive(message).await;
+        }
         loop {
-            if self.id == "alex" {
-                let (response_tx, mut response_rx) =
-                    mpsc::channel::<Result<(), ClientSendError>>(1);
-                if let Err(e) = self
-                    .client_tx
-                    .send(ClientCommand::Send(
-                        to.to_owned(),
-                        body.to_owned(),
-                        response_tx,
-                    ))
-                    .await
-                {
-                    eprintln!("[CLIENT][{}] Failed forwarding send request after fetching missing user from directory: {e}", &self.id);
-                } else {
-                    match response_rx.recv().await {
-                        Some(Err(e)) => {
-                            eprintln!(
-                                "[USER][{}] Client failed to send message to \"{to}\": {e}",
-                                &self.id
-                            );
-                        }
-                        None => {
-                            eprintln!(
-                                "[USER][{}] Response channel closed before receiving acknowledgement that message was sent to user with id \"{to}\"",
-                                &self.id
-                            );
-                        }
-                        _ => {}
+            let message = tokio::select! {
+                message = async {
+                    match &mut self.inbox_rx {
+                        Some(inbox_rx) => inbox_rx.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => message,
+                _ = async {
+                    match until {
+                        Some(until) => sleep_until(until).await,
+                        None => std::future::pending().await,
+                    }
+                } => return,
+            };
+            match message {
+                Some(message) => self.receive(message).await,
+                None => {
+                    self.inbox_rx = None;
+                    if until.is_none() {
+                        return;
                     }
                 }
             }
+        }
+    }
+
+    async fn receive(&mut self, message: ReceivedMessage) {
+        match &message.from {
+            Some(from) => println!(
+                "[USER][{}] Got message from \"{from}\": {}",
+                &self.id, message.body
+            ),
+            None => println!(
+                "[USER][{}] Got message from anonymous sender: {}",
+                &self.id, message.body
+            ),
+        }
+        if self.auto_reply && message.surb.is_some() {
+            let body = format!("Re: {}", message.body);
+            self.reply(&message, &body).await;
+        }
+    }
+
+    // Replies to a received message through the SURB it came with,
+    // without learning who sent it
+    pub async fn reply(&mut self, message: &ReceivedMessage, body: &str) {
+        let Some(surb) = message.surb else {
+            eprintln!(
+                "[USER][{}] Cannot reply to a message that came without a SURB",
+                &self.id
+            );
+            return;
+        };
+        let (response_tx, mut response_rx) = mpsc::channel::<Result<(), ClientSendError>>(1);
+        if let Err(e) = self
+            .client_tx
+            .send(ClientCommand::Reply(surb, body.to_owned(), response_tx))
+            .await
+        {
+            eprintln!(
+                "[USER][{}] Failed instructing client to reply to message: {e}",
+                &self.id
+            );
+            return;
+        }
+        match self.response(&mut response_rx).await {
+            Some(Err(e)) => {
+                eprintln!(
+                    "[USER][{}] Client failed to reply to message: {e}",
+                    &self.id
+                );
+            }
+            None => {
+                eprintln!(
+                    "[USER][{}] Response channel closed before receiving acknowledgement that reply was sent",
+                    &self.id
+                );
+            }
+            _ => {}
+        }
+    }
+
+    // Waits for the client's response to a request, meanwhile taking in
+    // the messages the client hands over so that it never waits on a
+    // full inbox while the user waits on it
+    async fn response<T>(&mut self, response_rx: &mut MpscReceiver<T>) -> Option<T> {
+        loop {
+            tokio::select! {
+                response = response_rx.recv() => return response,
+                message = async {
+                    match &mut self.inbox_rx {
+                        Some(inbox_rx) => inbox_rx.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => match message {
+                    Some(message) => self.backlog.push_back(message),
+                    None => self.inbox_rx = None,
+                },
+            }
+        }
+    }
+
+    // Sends a message, routed over `path_length` hops if given, or the
+    // network's path length otherwise
+    async fn send(&mut self, to: &str, body: &str, path_length: Option<usize>) {
+        let (response_tx, mut response_rx) = mpsc::channel::<Result<u64, ClientSendError>>(1);
+        if let Err(e) = self
+            .client_tx
+            .send(ClientCommand::Send(
+                to.to_owned(),
+                body.to_owned(),
+                path_length,
+                response_tx,
+            ))
+            .await
+        {
+            eprintln!(
+                "[USER][{}] Failed instructing client to send message to \"{to}\": {e}",
+                &self.id
+            );
+        } else {
+            match self.response(&mut response_rx).await {
+                Some(Ok(message_id)) => {
+                    println!("[USER][{}] Sent message {message_id} to \"{to}\"", &self.id);
+                }
+                Some(Err(e)) => {
+                    eprintln!(
+                        "[USER][{}] Client failed to send message to \"{to}\": {e}",
+                        &self.id
+                    );
+                }
+                None => {
+                    eprintln!(
+                        "[USER][{}] Response channel closed before receiving acknowledgement that message was sent to user with id \"{to}\"",
+                        &self.id
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Logs what came of each of the user's messages until the client goes
+// away
+async fn log_delivery_reports(id: String, delivery_rx: Option<MpscReceiver<DeliveryReport>>) {
+    let Some(mut delivery_rx) = delivery_rx else {
+        return;
+    };
+    while let Some(report) = delivery_rx.recv().await {
+        match report.status {
+            DeliveryStatus::Delivered(attempts) => println!(
+                "[USER][{id}] Message {} to \"{}\" was delivered after {attempts} attempts",
+                report.id, report.to
+            ),
+            DeliveryStatus::Failed(attempts) => eprintln!(
+                "[USER][{id}] Message {} to \"{}\" was not delivered after {attempts} attempts",
+                report.id, report.to
+            ),
+        }
+    }
+}
 
-            sleep(Duration::from_millis(interval_millis)).await;
+// Builds a message body for the given recipient, padded or truncated
+// to a random length within the configured size range
+fn generate_body(to: &str, size: Option<&MessageSize>, rng: &mut StdRng) -> String {
+    let greeting = format!("Hello, {to}! ");
+    match size {
+        Some(size) => {
+            let len = rng.random_range(size.min_bytes..=size.max_bytes.max(size.min_bytes));
+            greeting.chars().cycle().take(len).collect()
         }
+        None => greeting.trim_end().to_owned(),
     }
 }
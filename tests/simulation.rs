@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use simulation::{
+    config::{Client, ClockMode, Recipients, Traffic},
+    Simulation,
+};
+
+// A run defined entirely through the builder, without a config file
+#[test]
+fn runs_simulation_built_without_config_file() {
+    let mut builder = Simulation::builder()
+        .seed(7)
+        .clock_mode(ClockMode::Simulated)
+        .duration(Duration::from_secs(30))
+        .drain(Duration::from_secs(20))
+        .metrics(false)
+        .client(Client {
+            id: "alice".to_owned(),
+            traffic: Some(Traffic {
+                recipients: Some(Recipients::Fixed(vec!["bob".to_owned()])),
+                interval_millis: Some(5000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    for id in ["bob", "carol", "dave", "erin"] {
+        builder = builder.client(Client {
+            id: id.to_owned(),
+            ..Default::default()
+        });
+    }
+    let summary = builder.build().run_blocking().unwrap();
+    // One message every 5s over 30s, each of them either delivered or
+    // dropped by the end of the drain
+    assert_eq!(summary.sent, 6);
+    assert_eq!(summary.delivered + summary.dropped, summary.sent);
+}